@@ -0,0 +1,285 @@
+//! `Joque` against `Mutex<VecDeque>` and `std::sync::mpsc`, as CSV.
+//!
+//! Four workloads, each at 1 to 64 threads:
+//!
+//! - `lifo`: every thread pushes and pops at the front, in pairs.
+//! - `fifo`: every thread but one pushes at the back, and the last pops every item off the
+//!   front; on one thread it alternates. The only shape `mpsc` can take, so it joins here.
+//! - `steal`: thread 0 owns the back and works it like a stack, while every other thread
+//!   steals from the front.
+//! - `both`: every thread picks one of the four operations at random each time, on a deque
+//!   prefilled halfway.
+//!
+//! Every row is one workload on one queue at one thread count: how many operations ran,
+//! throughput in millions of operations a second, and percentiles of single-operation
+//! latency. An operation is one attempt: a pop that finds nothing, or a push turned away,
+//! counts. The exception is `fifo`, where producers retry until each item gets in and the
+//! consumer until it has them all, and each item counts once. Timing is `std::time` only,
+//! and every operation is timed on its own, which adds the clock's own overhead to each
+//! latency but leaves the comparison fair.
+//!
+//! ```text
+//! cargo bench --bench compare > compare.csv
+//! JOQUE_BENCH_OPS=2000 cargo bench --bench compare      # per thread, default 20000
+//! ```
+//!
+//! Without several cores to contend on, the numbers say very little.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Barrier, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use joque::Joque;
+
+const THREADS: [usize; 7] = [1, 2, 4, 8, 16, 32, 64];
+const WIDTH: u32 = 1025;
+
+type Ours = Joque<usize>;
+
+/// The four operations, with a push reporting whether it got in.
+trait Deque: Send + Sync + 'static {
+    const NAME: &'static str;
+    fn new() -> Self;
+    fn push_front(&self, item: usize) -> bool;
+    fn push_back(&self, item: usize) -> bool;
+    fn pop_front(&self) -> Option<usize>;
+    fn pop_back(&self) -> Option<usize>;
+}
+
+impl Deque for Joque<usize> {
+    const NAME: &'static str = "joque";
+
+    fn new() -> Self {
+        Joque::new(WIDTH)
+    }
+
+    fn push_front(&self, item: usize) -> bool {
+        self.try_push_front(Box::new(item)).is_ok()
+    }
+
+    fn push_back(&self, item: usize) -> bool {
+        self.try_push_back(Box::new(item)).is_ok()
+    }
+
+    fn pop_front(&self) -> Option<usize> {
+        Joque::pop_front(self).map(|item| *item)
+    }
+
+    fn pop_back(&self) -> Option<usize> {
+        Joque::pop_back(self).map(|item| *item)
+    }
+}
+
+/// Held to the same capacity as the `Joque`, so full means the same thing to both.
+struct Locked(Mutex<VecDeque<usize>>);
+
+impl Locked {
+    fn push(&self, item: usize, front: bool) -> bool {
+        let mut deque = self.0.lock().unwrap();
+        if deque.len() == WIDTH as usize - 1 {
+            return false;
+        }
+        if front { deque.push_front(item) } else { deque.push_back(item) }
+        true
+    }
+}
+
+impl Deque for Locked {
+    const NAME: &'static str = "mutex_vecdeque";
+
+    fn new() -> Self {
+        Locked(Mutex::new(VecDeque::with_capacity(WIDTH as usize)))
+    }
+
+    fn push_front(&self, item: usize) -> bool {
+        self.push(item, true)
+    }
+
+    fn push_back(&self, item: usize) -> bool {
+        self.push(item, false)
+    }
+
+    fn pop_front(&self) -> Option<usize> {
+        self.0.lock().unwrap().pop_front()
+    }
+
+    fn pop_back(&self) -> Option<usize> {
+        self.0.lock().unwrap().pop_back()
+    }
+}
+
+/// One thread's share of a run: how long each of its operations took, in nanoseconds.
+#[derive(Default)]
+struct Latencies(Vec<u64>);
+
+impl Latencies {
+    fn time<R>(&mut self, op: impl FnOnce() -> R) -> R {
+        let began = Instant::now();
+        let out = op();
+        self.0.push(began.elapsed().as_nanos() as u64);
+        out
+    }
+}
+
+/// Runs `work(shared, thread)` on `threads` threads at once, returning the time from the
+/// first one starting to the last one finishing, and every latency they recorded.
+fn race<S: Send + Sync + 'static>(
+    threads: usize,
+    shared: S,
+    work: impl Fn(&S, usize, &mut Latencies) + Send + Sync + 'static,
+) -> (Duration, Vec<u64>) {
+    let shared = Arc::new(shared);
+    let work = Arc::new(work);
+    let start = Arc::new(Barrier::new(threads));
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let (shared, work, start) = (shared.clone(), work.clone(), start.clone());
+            std::thread::spawn(move || {
+                let mut latencies = Latencies::default();
+                start.wait();
+                let began = Instant::now();
+                work(&shared, t, &mut latencies);
+                (began, Instant::now(), latencies.0)
+            })
+        })
+        .collect();
+    let runs: Vec<_> = workers.into_iter().map(|worker| worker.join().unwrap()).collect();
+    let began = runs.iter().map(|run| run.0).min().unwrap();
+    let ended = runs.iter().map(|run| run.1).max().unwrap();
+    (ended - began, runs.into_iter().flat_map(|run| run.2).collect())
+}
+
+fn lifo<D: Deque>(threads: usize, ops: usize) -> (Duration, Vec<u64>) {
+    race(threads, D::new(), move |deque, t, lat| {
+        for i in 0..ops / 2 {
+            lat.time(|| deque.push_front(t + i));
+            std::hint::black_box(lat.time(|| deque.pop_front()));
+        }
+    })
+}
+
+fn fifo<D: Deque>(threads: usize, ops: usize) -> (Duration, Vec<u64>) {
+    race(threads, D::new(), move |deque, t, lat| {
+        if threads == 1 {
+            for i in 0..ops / 2 {
+                lat.time(|| deque.push_back(i));
+                std::hint::black_box(lat.time(|| deque.pop_front()));
+            }
+        } else if t == 0 {
+            for _ in 0..(threads - 1) * ops {
+                std::hint::black_box(lat.time(|| {
+                    loop {
+                        if let Some(item) = deque.pop_front() {
+                            break item;
+                        }
+                        // with more threads than cores, the producer may need the core
+                        std::thread::yield_now();
+                    }
+                }));
+            }
+        } else {
+            for i in 0..ops {
+                lat.time(|| {
+                    while !deque.push_back(t + i) {
+                        std::thread::yield_now();
+                    }
+                });
+            }
+        }
+    })
+}
+
+/// `fifo` over a channel: senders for the producers, the receiver for the consumer.
+fn fifo_mpsc(threads: usize, ops: usize) -> (Duration, Vec<u64>) {
+    let (tx, rx) = mpsc::channel::<usize>();
+    let ends = (Mutex::new(tx), Mutex::new(Some(rx)));
+    race(threads, ends, move |(tx, rx), t, lat| {
+        let tx = tx.lock().unwrap().clone();
+        if threads == 1 {
+            let rx = rx.lock().unwrap().take().unwrap();
+            for i in 0..ops / 2 {
+                lat.time(|| tx.send(i).unwrap());
+                std::hint::black_box(lat.time(|| rx.try_recv().ok()));
+            }
+        } else if t == 0 {
+            drop(tx);
+            let rx = rx.lock().unwrap().take().unwrap();
+            for _ in 0..(threads - 1) * ops {
+                std::hint::black_box(lat.time(|| rx.recv().unwrap()));
+            }
+        } else {
+            for i in 0..ops {
+                lat.time(|| tx.send(t + i).unwrap());
+            }
+        }
+    })
+}
+
+fn steal<D: Deque>(threads: usize, ops: usize) -> (Duration, Vec<u64>) {
+    race(threads, D::new(), move |deque, t, lat| {
+        if t == 0 {
+            for i in 0..ops / 2 {
+                lat.time(|| deque.push_back(i));
+                std::hint::black_box(lat.time(|| deque.pop_back()));
+            }
+        } else {
+            for _ in 0..ops {
+                std::hint::black_box(lat.time(|| deque.pop_front()));
+            }
+        }
+    })
+}
+
+fn both<D: Deque>(threads: usize, ops: usize) -> (Duration, Vec<u64>) {
+    let deque = D::new();
+    for i in 0..WIDTH as usize / 2 {
+        deque.push_back(i);
+    }
+    race(threads, deque, move |deque, t, lat| {
+        // xorshift64*, seeded per thread
+        let mut rng = (t as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        for i in 0..ops {
+            rng ^= rng >> 12;
+            rng ^= rng << 25;
+            rng ^= rng >> 27;
+            match rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 62 {
+                0 => std::hint::black_box(lat.time(|| deque.push_front(i))),
+                1 => std::hint::black_box(lat.time(|| deque.push_back(i))),
+                2 => std::hint::black_box(lat.time(|| deque.pop_front()).is_some()),
+                _ => std::hint::black_box(lat.time(|| deque.pop_back()).is_some()),
+            };
+        }
+    })
+}
+
+/// One CSV row.
+fn report(workload: &str, queue: &str, threads: usize, (elapsed, mut latencies): (Duration, Vec<u64>)) {
+    latencies.sort_unstable();
+    let at = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "{workload},{queue},{threads},{},{:.3},{},{},{},{},{}",
+        latencies.len(),
+        latencies.len() as f64 / elapsed.as_secs_f64() / 1e6,
+        at(0.5),
+        at(0.9),
+        at(0.99),
+        at(0.999),
+        latencies[latencies.len() - 1],
+    );
+}
+
+fn main() {
+    let ops = std::env::var("JOQUE_BENCH_OPS").map_or(20_000, |ops| ops.parse().expect("JOQUE_BENCH_OPS is a count"));
+    println!("workload,queue,threads,ops,mops_per_sec,p50_ns,p90_ns,p99_ns,p999_ns,max_ns");
+    for threads in THREADS {
+        report("lifo", Ours::NAME, threads, lifo::<Ours>(threads, ops));
+        report("lifo", Locked::NAME, threads, lifo::<Locked>(threads, ops));
+        report("fifo", Ours::NAME, threads, fifo::<Ours>(threads, ops));
+        report("fifo", Locked::NAME, threads, fifo::<Locked>(threads, ops));
+        report("fifo", "mpsc", threads, fifo_mpsc(threads, ops));
+        report("steal", Ours::NAME, threads, steal::<Ours>(threads, ops));
+        report("steal", Locked::NAME, threads, steal::<Locked>(threads, ops));
+        report("both", Ours::NAME, threads, both::<Ours>(threads, ops));
+        report("both", Locked::NAME, threads, both::<Locked>(threads, ops));
+    }
+}
@@ -0,0 +1,163 @@
+//! Throughput under contention, up to 64 threads.
+//!
+//! The first table replays what one push does to the deque's shared header (read the
+//! read-only fields, CAS the extents, bump the op tag and the claim cursor) against two
+//! layouts: everything on one cache line, as `Joque` used to be, and each counter on its
+//! own. The second runs push/pop pairs through a real `Joque` and `InlineJoque`; build
+//! it again with `--features padded-slots` to compare slot layouts.
+//!
+//! ```text
+//! cargo bench --bench contention
+//! cargo bench --bench contention --features padded-slots
+//! ```
+//!
+//! Without several cores to contend on, the numbers say very little.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+use joque::{InlineJoque, Joque};
+
+const THREADS: [usize; 5] = [1, 4, 16, 32, 64];
+const OPS_PER_THREAD: usize = 20_000;
+
+/// The header as it was: read-mostly fields and all three counters within 24 bytes.
+#[derive(Default)]
+struct Packed {
+    capacity: u32,
+    leftright: AtomicU64,
+    op_id: AtomicU32,
+    idx: AtomicU32,
+}
+
+#[repr(align(128))]
+#[derive(Default)]
+struct Line<T>(T);
+
+/// The header as it is: no shared tag counter, and each remaining counter on a line of its own.
+#[derive(Default)]
+struct Padded {
+    capacity: u32,
+    leftright: Line<AtomicU64>,
+    idx: Line<AtomicU32>,
+}
+
+trait Header: Default + Send + Sync + 'static {
+    fn op(&self);
+}
+
+impl Header for Packed {
+    fn op(&self) {
+        let capacity = std::hint::black_box(self.capacity) as u64 + 1;
+        let _ = self.leftright.fetch_update(Ordering::AcqRel, Ordering::Acquire, |lr| Some((lr + 1) % capacity));
+        self.op_id.fetch_add(1, Ordering::Relaxed);
+        self.idx.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Header for Padded {
+    fn op(&self) {
+        let capacity = std::hint::black_box(self.capacity) as u64 + 1;
+        let _ = self.leftright.0.fetch_update(Ordering::AcqRel, Ordering::Acquire, |lr| Some((lr + 1) % capacity));
+        self.idx.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs `work` on `threads` threads at once, returning the time from the first one starting
+/// to the last one finishing.
+fn race<S: Send + Sync + 'static>(threads: usize, shared: S, work: fn(&S, usize)) -> Duration {
+    let shared = Arc::new(shared);
+    let start = Arc::new(Barrier::new(threads));
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let shared = shared.clone();
+            let start = start.clone();
+            std::thread::spawn(move || {
+                start.wait();
+                let began = Instant::now();
+                work(&shared, t);
+                (began, Instant::now())
+            })
+        })
+        .collect();
+    let spans: Vec<_> = workers.into_iter().map(|worker| worker.join().unwrap()).collect();
+    let began = spans.iter().map(|span| span.0).min().unwrap();
+    let ended = spans.iter().map(|span| span.1).max().unwrap();
+    ended - began
+}
+
+fn ns_per_op(elapsed: Duration, threads: usize) -> f64 {
+    elapsed.as_nanos() as f64 / (threads * OPS_PER_THREAD) as f64
+}
+
+fn header<H: Header>(threads: usize) -> f64 {
+    let elapsed = race(threads, H::default(), |header, _| {
+        for _ in 0..OPS_PER_THREAD {
+            header.op();
+        }
+    });
+    ns_per_op(elapsed, threads)
+}
+
+/// Pushes at the back and pops at the front; `cells` of 0 is the plain deque, anything
+/// more lets it fall back to flat combining.
+fn boxed(threads: usize, cells: usize) -> f64 {
+    let elapsed = race(threads, Joque::new(1024).combining(cells), |deque, t| {
+        for i in 0..OPS_PER_THREAD / 2 {
+            deque.push_back(Box::new(t + i));
+            std::hint::black_box(deque.pop_front());
+        }
+    });
+    ns_per_op(elapsed, threads)
+}
+
+fn inline(threads: usize) -> f64 {
+    let elapsed = race(threads, InlineJoque::new(1024), |deque, t| {
+        for i in 0..OPS_PER_THREAD / 2 {
+            deque.push_back((t + i) as u16);
+            std::hint::black_box(deque.pop_front());
+        }
+    });
+    ns_per_op(elapsed, threads)
+}
+
+/// Pushes and pops at the same end, the pattern elimination pairs off; `cells` of 0 is
+/// the plain deque.
+fn stack(threads: usize, cells: usize) -> f64 {
+    let elapsed = race(threads, Joque::new(1024).eliminating(cells), |deque, t| {
+        for i in 0..OPS_PER_THREAD / 2 {
+            deque.push_front(Box::new(t + i));
+            std::hint::black_box(deque.pop_front());
+        }
+    });
+    ns_per_op(elapsed, threads)
+}
+
+fn main() {
+    println!("header traffic, ns/op");
+    println!("{:>8} {:>10} {:>10}", "threads", "packed", "padded");
+    for threads in THREADS {
+        println!("{threads:>8} {:>10.1} {:>10.1}", header::<Packed>(threads), header::<Padded>(threads));
+    }
+
+    let slots = if cfg!(feature = "padded-slots") { "padded" } else { "packed" };
+    println!();
+    println!("push/pop pairs, {slots} slots, ns/op");
+    println!("{:>8} {:>10} {:>10} {:>10}", "threads", "Joque", "combining", "Inline");
+    for threads in THREADS {
+        println!(
+            "{threads:>8} {:>10.1} {:>10.1} {:>10.1}",
+            boxed(threads, 0),
+            boxed(threads, 64),
+            inline(threads)
+        );
+    }
+
+    println!();
+    println!("stack-like pairs, ns/op");
+    println!("{:>8} {:>10} {:>12}", "threads", "plain", "eliminating");
+    for threads in THREADS {
+        println!("{threads:>8} {:>10.1} {:>12.1}", stack(threads, 0), stack(threads, 8));
+    }
+}
@@ -0,0 +1,219 @@
+//! What a deque does between attempts at a word another thread got to first.
+//!
+//! Every operation retries in two places: the CAS on the extents, when another operation
+//! moved them in between, and the wait on its ring slot, when the operation that owned the
+//! slot before it hasn't finished. Retrying straight away keeps every contender hammering the
+//! same line; a [`Backoff`] decides how long to step aside first. [`Joque`](crate::Joque)
+//! takes one per deque and counts the retries in [`Retries`].
+
+#[cfg(feature = "std")]
+use core::time::Duration;
+
+use crate::sync::*;
+
+/// A policy for waiting out contention.
+pub trait Backoff {
+    /// Called before retry number `retries + 1` of the same attempt, so `retries` starts at
+    /// zero and grows for as long as the word stays contended.
+    fn snooze(&self, retries: u32);
+}
+
+/// Lets several deques share one policy.
+impl<B: Backoff + ?Sized> Backoff for &B {
+    fn snooze(&self, retries: u32) {
+        (**self).snooze(retries)
+    }
+}
+
+/// One pause instruction per retry.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spin;
+
+impl Backoff for Spin {
+    fn snooze(&self, _retries: u32) {
+        spin(1);
+    }
+}
+
+/// Twice as many pause instructions each retry, up to `2^limit`.
+#[derive(Clone, Copy, Debug)]
+pub struct Exponential {
+    pub limit: u32,
+}
+
+impl Default for Exponential {
+    fn default() -> Self {
+        Exponential { limit: 6 }
+    }
+}
+
+impl Backoff for Exponential {
+    fn snooze(&self, retries: u32) {
+        spin(1 << retries.min(self.limit));
+    }
+}
+
+/// Gives the rest of the time slice away every retry, or spins once without `std`. This is
+/// what a deque does unless told otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Yield;
+
+impl Backoff for Yield {
+    fn snooze(&self, _retries: u32) {
+        relax();
+    }
+}
+
+/// Backs off like [`Exponential`] for the first `spins` retries, then parks the thread for
+/// `park` at a time. Nothing wakes it early, so `park` is the latency a long wait pays.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub struct ParkAfter {
+    pub spins: u32,
+    pub park: Duration,
+}
+
+#[cfg(feature = "std")]
+impl Default for ParkAfter {
+    fn default() -> Self {
+        ParkAfter { spins: 10, park: Duration::from_micros(50) }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Backoff for ParkAfter {
+    fn snooze(&self, retries: u32) {
+        if retries < self.spins {
+            Exponential::default().snooze(retries);
+        } else {
+            park(self.park);
+        }
+    }
+}
+
+#[cfg(all(feature = "std", not(loom)))]
+fn park(dur: Duration) {
+    std::thread::sleep(dur);
+}
+
+#[cfg(all(feature = "std", loom))]
+fn park(_dur: Duration) {
+    relax();
+}
+
+/// How many times a deque's operations have had to retry, summed over its lifetime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Retries {
+    /// CASes on the extents that lost to another operation.
+    pub extents: usize,
+    /// Rounds spent waiting for the previous owner of a ring slot to finish with it.
+    pub slots: usize,
+    /// Rescans of the backing table for a free record.
+    pub records: usize,
+    /// Operations that stopped retrying and were carried out by a combiner; see
+    /// [`Joque::combining`](crate::Joque::combining).
+    pub combined: usize,
+}
+
+/// The live counters behind [`Retries`].
+#[allow(dead_code)]
+pub(crate) struct RetryCounts {
+    pub(crate) extents: AtomicUsize,
+    pub(crate) slots: AtomicUsize,
+    pub(crate) records: AtomicUsize,
+}
+
+#[allow(dead_code)]
+impl RetryCounts {
+    pub(crate) fn new() -> Self {
+        RetryCounts {
+            extents: AtomicUsize::new(0),
+            slots: AtomicUsize::new(0),
+            records: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Retries {
+        Retries {
+            extents: self.extents.load(Ordering::Relaxed),
+            slots: self.slots.load(Ordering::Relaxed),
+            records: self.records.load(Ordering::Relaxed),
+            combined: 0,
+        }
+    }
+}
+
+/// One operation's run of attempts at a contended word.
+pub(crate) struct Attempts<'a, B: Backoff + ?Sized> {
+    backoff: &'a B,
+    retries: u32,
+}
+
+impl<'a, B: Backoff + ?Sized> Attempts<'a, B> {
+    pub(crate) fn new(backoff: &'a B) -> Self {
+        Attempts { backoff, retries: 0 }
+    }
+
+    pub(crate) fn snooze(&mut self) {
+        self.backoff.snooze(self.retries);
+        self.retries = self.retries.saturating_add(1);
+    }
+
+    /// Snoozes so far in this run.
+    #[allow(dead_code)]
+    pub(crate) fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Adds this run's retries to `counter`, skipping the shared write when there were none.
+    #[allow(dead_code)]
+    pub(crate) fn tally(&self, counter: &AtomicUsize) {
+        if self.retries != 0 {
+            counter.fetch_add(self.retries as usize, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    /// Remembers every `retries` it was asked to wait out.
+    #[derive(Default)]
+    struct Log(std::sync::Mutex<Vec<u32>>);
+
+    impl Backoff for Log {
+        fn snooze(&self, retries: u32) {
+            self.0.lock().unwrap().push(retries);
+        }
+    }
+
+    #[test]
+    fn attempts_count_up_and_tally_once() {
+        let log = Log::default();
+        let counter = AtomicUsize::new(0);
+
+        let mut attempts = Attempts::new(&log);
+        attempts.tally(&counter);
+        assert_eq!(0, counter.load(Ordering::Relaxed));
+        for _ in 0..4 {
+            attempts.snooze();
+        }
+        attempts.tally(&counter);
+
+        assert_eq!(vec![0, 1, 2, 3], *log.0.lock().unwrap());
+        assert_eq!(4, counter.load(Ordering::Relaxed));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn park_after_spins_first() {
+        let backoff = ParkAfter { spins: 2, park: Duration::from_millis(20) };
+        let start = std::time::Instant::now();
+        backoff.snooze(0);
+        backoff.snooze(1);
+        assert!(start.elapsed() < Duration::from_millis(20));
+        backoff.snooze(2);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
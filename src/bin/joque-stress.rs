@@ -0,0 +1,329 @@
+//! Soak-tests a `Joque` for as long as you like.
+//!
+//! Worker threads hammer one deque with a weighted mix of the four operations. Every item
+//! carries its producer, a per-producer sequence number, and a checksum of the two, and
+//! every thread keeps a tally per producer of what it pushed and what it popped. Once each
+//! reporting interval the workers stop, and with nothing in flight the deque has to
+//! validate, its length has to match what it holds, and for every producer what got in has
+//! to equal what came out plus what's still inside: in count, in the sum of sequence
+//! numbers, and in the sum of their squares. Then the workers start again on the same deque.
+//!
+//! ```text
+//! joque-stress --threads 16 --width 4096 --mix 1,1,1,1 --duration 3600 --seed 7
+//! ```
+//!
+//! Anything off is printed as an `anomaly` line at the check that finds it; the exit status is
+//! nonzero if there were any.
+
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use joque::Joque;
+
+const USAGE: &str = "\
+usage: joque-stress [options]
+  --threads N       worker threads (default: available cores)
+  --width N         ring slots, at least 5; holds N - 1 items (default 4096)
+  --mix A,B,C,D     weights of push_front, push_back, pop_front, pop_back (default 1,1,1,1)
+  --duration SECS   stop after this long; 0 runs until killed (default 0)
+  --interval SECS   how often to check and report (default 5)
+  --seed N          seed for every thread's op choices (default: the clock)
+  --eliminating N   give each end an elimination array of N cells
+  --combining N     let contended operations fall back to N combining cells";
+
+struct Config {
+    threads: usize,
+    width: u32,
+    mix: [u64; 4],
+    duration: Option<Duration>,
+    interval: Duration,
+    seed: u64,
+    eliminating: usize,
+    combining: usize,
+}
+
+impl Config {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let mut config = Config {
+            threads: thread::available_parallelism().map_or(4, |cores| cores.get()),
+            width: 4096,
+            mix: [1; 4],
+            duration: None,
+            interval: Duration::from_secs(5),
+            seed: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |now| now.as_nanos() as u64),
+            eliminating: 0,
+            combining: 0,
+        };
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(String::new());
+            }
+            let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+            let number = || value.parse::<u64>().map_err(|_| format!("{flag} takes a number, not {value:?}"));
+            match flag.as_str() {
+                "--threads" => config.threads = number()?.max(1) as usize,
+                "--width" => {
+                    config.width = u32::try_from(number()?)
+                        .ok()
+                        .filter(|width| (5..=Joque::<Item>::MAX_WIDTH).contains(width))
+                        .ok_or_else(|| format!("--width takes 5 to {} slots, not {value}", Joque::<Item>::MAX_WIDTH))?;
+                }
+                "--duration" => config.duration = Some(Duration::from_secs(number()?)).filter(|d| !d.is_zero()),
+                "--interval" => config.interval = Duration::from_secs(number()?.max(1)),
+                "--seed" => config.seed = number()?,
+                "--eliminating" => config.eliminating = number()? as usize,
+                "--combining" => config.combining = number()? as usize,
+                "--mix" => {
+                    let weights: Vec<u64> = value
+                        .split(',')
+                        .map(|weight| weight.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| format!("--mix takes four comma-separated weights, not {value:?}"))?;
+                    config.mix = weights
+                        .try_into()
+                        .ok()
+                        .filter(|mix: &[u64; 4]| mix.iter().sum::<u64>() > 0)
+                        .ok_or_else(|| format!("--mix takes four weights, not all zero, not {value:?}"))?;
+                }
+                _ => return Err(format!("unknown flag {flag}")),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// What every item carries, so a pop can tell where it came from and that it's intact.
+struct Item {
+    producer: u32,
+    seq: u64,
+    check: u64,
+}
+
+/// splitmix64's finalizer, as the checksum and to seed each thread.
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl Item {
+    fn new(producer: u32, seq: u64) -> Box<Item> {
+        Box::new(Item { producer, seq, check: mix64((producer as u64) << 48 ^ seq) })
+    }
+
+    fn intact(&self) -> bool {
+        self.check == mix64((self.producer as u64) << 48 ^ self.seq)
+    }
+}
+
+/// Items from one producer, summed three ways so a loss and a duplicate can't cancel out.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+struct Tally {
+    count: u64,
+    sum: u64,
+    squares: u64,
+}
+
+impl Tally {
+    fn add(&mut self, seq: u64) {
+        self.count += 1;
+        self.sum = self.sum.wrapping_add(seq);
+        self.squares = self.squares.wrapping_add(seq.wrapping_mul(seq));
+    }
+
+    fn merge(&mut self, other: &Tally) {
+        self.count += other.count;
+        self.sum = self.sum.wrapping_add(other.sum);
+        self.squares = self.squares.wrapping_add(other.squares);
+    }
+}
+
+/// One worker's interval: its next sequence number carries over, the rest starts fresh.
+struct Worker {
+    producer: u32,
+    next_seq: u64,
+    rng: u64,
+    pushed: Tally,
+    popped: Vec<Tally>,
+    ops: u64,
+    anomalies: Vec<String>,
+}
+
+impl Worker {
+    fn run(&mut self, deque: &Joque<Item>, mix: &[u64; 4], until: Instant) {
+        let total: u64 = mix.iter().sum();
+        while Instant::now() < until {
+            for _ in 0..256 {
+                // xorshift64*
+                self.rng ^= self.rng >> 12;
+                self.rng ^= self.rng << 25;
+                self.rng ^= self.rng >> 27;
+                let mut roll = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) % total;
+                let op = mix.iter().position(|&weight| roll < weight || { roll -= weight; false }).unwrap();
+                self.ops += 1;
+                let popped = match op {
+                    0 | 1 => {
+                        let item = Item::new(self.producer, self.next_seq);
+                        let pushed =
+                            if op == 0 { deque.try_push_front(item) } else { deque.try_push_back(item) };
+                        if pushed.is_ok() {
+                            self.pushed.add(self.next_seq);
+                            self.next_seq += 1;
+                        }
+                        continue;
+                    }
+                    2 => deque.pop_front(),
+                    _ => deque.pop_back(),
+                };
+                if let Some(item) = popped {
+                    self.tally(&item);
+                }
+            }
+        }
+    }
+
+    fn tally(&mut self, item: &Item) {
+        match self.popped.get_mut(item.producer as usize) {
+            Some(tally) if item.intact() => tally.add(item.seq),
+            _ => self.anomalies.push(format!(
+                "popped a corrupt item: producer {} seq {} check {:#x}",
+                item.producer, item.seq, item.check
+            )),
+        }
+    }
+}
+
+/// Prints one anomaly as it's found, and counts it towards the exit status.
+fn anomaly(anomalies: &mut usize, message: String, elapsed: Duration) {
+    *anomalies += 1;
+    println!("[{:>7.1}s] anomaly: {message}", elapsed.as_secs_f64());
+}
+
+fn main() -> ExitCode {
+    let config = match Config::parse(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{err}");
+            }
+            eprintln!("{USAGE}");
+            return if err.is_empty() { ExitCode::SUCCESS } else { ExitCode::from(2) };
+        }
+    };
+    println!(
+        "joque-stress: {} threads, width {}, mix {:?}, seed {}, {}",
+        config.threads,
+        config.width,
+        config.mix,
+        config.seed,
+        config.duration.map_or("until killed".into(), |d| format!("{}s", d.as_secs()))
+    );
+
+    let mut deque = Joque::new(config.width);
+    if config.eliminating > 0 {
+        deque = deque.eliminating(config.eliminating);
+    }
+    if config.combining > 0 {
+        deque = deque.combining(config.combining);
+    }
+    let mut workers: Vec<Worker> = (0..config.threads)
+        .map(|t| Worker {
+            producer: t as u32,
+            next_seq: 0,
+            rng: mix64(config.seed ^ t as u64) | 1,
+            pushed: Tally::default(),
+            popped: vec![Tally::default(); config.threads],
+            ops: 0,
+            anomalies: Vec::new(),
+        })
+        .collect();
+    let mut pushed = vec![Tally::default(); config.threads];
+    let mut popped = vec![Tally::default(); config.threads];
+    let (mut total_ops, mut anomalies) = (0u64, 0usize);
+
+    let began = Instant::now();
+    loop {
+        let interval_began = Instant::now();
+        let mut until = interval_began + config.interval;
+        if let Some(duration) = config.duration {
+            until = until.min(began + duration);
+        }
+        let panicked = thread::scope(|s| {
+            let ths: Vec<_> = workers
+                .iter_mut()
+                .map(|worker| {
+                    let deque = &deque;
+                    s.spawn(move || worker.run(deque, &config.mix, until))
+                })
+                .collect();
+            ths.into_iter().map(|th| th.join()).filter(Result::is_err).count() != 0
+        });
+        let elapsed = began.elapsed();
+        if panicked {
+            anomaly(&mut anomalies, "a worker panicked; stopping".into(), elapsed);
+            break;
+        }
+
+        // quiescent: fold this interval's tallies in and check what's left inside
+        let mut ops = 0;
+        for worker in &mut workers {
+            pushed[worker.producer as usize].merge(&std::mem::take(&mut worker.pushed));
+            for (total, tally) in popped.iter_mut().zip(&mut worker.popped) {
+                total.merge(&std::mem::take(tally));
+            }
+            ops += std::mem::take(&mut worker.ops);
+            for message in worker.anomalies.drain(..) {
+                anomaly(&mut anomalies, message, elapsed);
+            }
+        }
+        total_ops += ops;
+        let report = deque.validate();
+        if !report.is_ok() {
+            anomaly(&mut anomalies, report.to_string(), elapsed);
+        }
+        let len = deque.len();
+        let mut inside = vec![Tally::default(); config.threads];
+        let mut held = Vec::with_capacity(len);
+        while let Some(item) = deque.pop_front() {
+            match inside.get_mut(item.producer as usize) {
+                Some(tally) if item.intact() => tally.add(item.seq),
+                _ => anomaly(&mut anomalies, format!("corrupt item left in the deque: producer {} seq {}", item.producer, item.seq), elapsed),
+            }
+            held.push(item);
+        }
+        if held.len() != len {
+            anomaly(&mut anomalies, format!("len said {len} but {} items came out", held.len()), elapsed);
+        }
+        for producer in 0..config.threads {
+            let mut out = popped[producer];
+            out.merge(&inside[producer]);
+            if out != pushed[producer] {
+                anomaly(&mut anomalies, 
+                    format!("producer {producer}: pushed {:?} but popped plus inside is {out:?}", pushed[producer]),
+                    elapsed,
+                );
+            }
+        }
+        for item in held {
+            if deque.try_push_back(item).is_err() {
+                anomaly(&mut anomalies, "couldn't put an item back after checking".into(), elapsed);
+            }
+        }
+
+        let secs = interval_began.elapsed().as_secs_f64();
+        println!(
+            "[{:>7.1}s] {:>8.3} Mops/s, {} ops so far, len {len}, anomalies {anomalies}",
+            elapsed.as_secs_f64(),
+            ops as f64 / secs / 1e6,
+            total_ops,
+        );
+        if config.duration.is_some_and(|duration| elapsed >= duration) {
+            break;
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    println!("{:?}", deque.stats());
+    if anomalies == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
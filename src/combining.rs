@@ -0,0 +1,242 @@
+//! Handing operations to one combining thread once the extents get too contended.
+//!
+//! Past a point, every thread retrying its own CAS on `leftright` only makes the next CAS
+//! less likely to land. Flat combining has the losers publish what they want in a request
+//! cell instead, and whichever of them takes the combiner lock carries out every published
+//! request in one pass, so a single thread works the extents on behalf of the whole crowd.
+//! Pushes and pops at the same end in one pass cancel out without touching the ring.
+//!
+//! The mode is adaptive. An operation joins in after losing [`COMBINE_AFTER`] CASes in a
+//! row, which marks the deque hot, and while it's hot new operations publish straight away.
+//! A pass that finds nobody but the combiner waiting cools it down again, and operations go
+//! back to reserving their own slots.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::backoff::{Attempts, Backoff};
+use crate::pad::CachePadded;
+use crate::sync::*;
+use crate::{Op, Record};
+
+/// Lost CASes on the extents after which an operation stops retrying and publishes.
+pub(crate) const COMBINE_AFTER: u32 = 4;
+
+// request cell states
+const FREE: u32 = 0;
+const CLAIMED: u32 = 1; // a thread is writing its request in
+const PENDING: u32 = 2;
+const DONE: u32 = 3;
+
+const OPS: [Op; 4] = [Op::PushFront, Op::PopFront, Op::PushBack, Op::PopBack];
+
+/// One published operation. A push's record goes in and comes back null once it's queued,
+/// or comes back untouched if the ring was full; a pop's comes back holding the item, or
+/// null if there was none.
+struct Request<T: ?Sized> {
+    state: AtomicU32,
+    op: AtomicU32, // index into `OPS`
+    record: AtomicPtr<Record<T>>,
+}
+
+/// The request cells and combiner lock, with no cells unless the deque was built
+/// [`combining`](crate::Joque::combining).
+pub(crate) struct Combining<T: ?Sized> {
+    requests: Vec<Request<T>>,
+    lock: CachePadded<AtomicBool>,
+    hot: CachePadded<AtomicBool>,
+    combined: CachePadded<AtomicUsize>,
+}
+
+impl<T: ?Sized> Combining<T> {
+    pub(crate) fn new(width: usize) -> Self {
+        Combining {
+            requests: (0..width)
+                .map(|_| Request {
+                    state: AtomicU32::new(FREE),
+                    op: AtomicU32::new(0),
+                    record: AtomicPtr::new(ptr::null_mut()),
+                })
+                .collect(),
+            lock: CachePadded::new(AtomicBool::new(false)),
+            hot: CachePadded::new(AtomicBool::new(false)),
+            combined: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Whether an operation should publish before it even tries the extents.
+    pub(crate) fn is_hot(&self) -> bool {
+        !self.requests.is_empty() && self.hot.load(Ordering::Relaxed)
+    }
+
+    /// Whether an operation that has lost `attempts` worth of CASes should publish now.
+    pub(crate) fn wants<B: Backoff + ?Sized>(&self, attempts: &Attempts<'_, B>) -> bool {
+        !self.requests.is_empty() && attempts.retries() >= COMBINE_AFTER
+    }
+
+    /// Operations carried out by a combiner so far, whether through the ring or cancelled
+    /// against each other.
+    pub(crate) fn combined(&self) -> usize {
+        self.combined.load(Ordering::Relaxed)
+    }
+
+    /// Publishes `op` on `record` and waits for a combiner to carry it out, becoming the
+    /// combiner if nobody else is. `apply` carries out one operation through the ring.
+    /// Returns `None`, having done nothing, when every request cell is taken.
+    pub(crate) fn submit<B: Backoff + ?Sized>(
+        &self,
+        op: Op,
+        record: *mut Record<T>,
+        attempts: &mut Attempts<'_, B>,
+        apply: impl Fn(Op, *mut Record<T>) -> *mut Record<T>,
+    ) -> Option<*mut Record<T>> {
+        let request = self.requests.iter().find(|request| {
+            request
+                .state
+                .compare_exchange(FREE, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        self.hot.store(true, Ordering::Relaxed);
+        request.op.store(OPS.iter().position(|&o| o == op).unwrap() as u32, Ordering::Relaxed);
+        request.record.store(record, Ordering::Relaxed);
+        request.state.store(PENDING, Ordering::Release);
+        loop {
+            if request.state.load(Ordering::Acquire) == DONE {
+                let out = request.record.load(Ordering::Relaxed);
+                request.state.store(FREE, Ordering::Release);
+                return Some(out);
+            }
+            if !self.lock.swap(true, Ordering::Acquire) {
+                self.combine(&apply);
+                self.lock.store(false, Ordering::Release);
+                continue;
+            }
+            attempts.snooze();
+        }
+    }
+
+    /// One combining pass over every pending request, under the lock.
+    fn combine(&self, apply: &impl Fn(Op, *mut Record<T>) -> *mut Record<T>) {
+        let mut served = 0;
+        for (i, request) in self.requests.iter().enumerate() {
+            if request.state.load(Ordering::Acquire) != PENDING {
+                continue;
+            }
+            let op = OPS[request.op.load(Ordering::Relaxed) as usize];
+            let record = request.record.load(Ordering::Relaxed);
+            // a push then a pop at the same end leaves the deque as it was
+            let partner = self.requests[i + 1..].iter().find(|other| {
+                other.state.load(Ordering::Acquire) == PENDING
+                    && cancels(op, OPS[other.op.load(Ordering::Relaxed) as usize])
+            });
+            if let Some(partner) = partner {
+                let pushed = if op.is_push() { record } else { partner.record.load(Ordering::Relaxed) };
+                let (push, pop) = if op.is_push() { (request, partner) } else { (partner, request) };
+                push.record.store(ptr::null_mut(), Ordering::Relaxed);
+                pop.record.store(pushed, Ordering::Relaxed);
+                push.state.store(DONE, Ordering::Release);
+                pop.state.store(DONE, Ordering::Release);
+                served += 2;
+                continue;
+            }
+            request.record.store(apply(op, record), Ordering::Relaxed);
+            request.state.store(DONE, Ordering::Release);
+            served += 1;
+        }
+        self.combined.fetch_add(served, Ordering::Relaxed);
+        if served <= 1 {
+            // nobody else was waiting; the crowd has thinned out
+            self.hot.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether any request cell is in use right now.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.requests.iter().all(|request| request.state.load(Ordering::Relaxed) == FREE)
+    }
+}
+
+/// Whether `a` and `b` are a push and a pop at the same end.
+fn cancels(a: Op, b: Op) -> bool {
+    matches!(
+        (a, b),
+        (Op::PushFront, Op::PopFront)
+            | (Op::PopFront, Op::PushFront)
+            | (Op::PushBack, Op::PopBack)
+            | (Op::PopBack, Op::PushBack)
+    )
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use core::cell::RefCell;
+
+    fn record(item: u32) -> *mut Record<u32> {
+        Box::into_raw(Box::new((0, Some(Box::new(item)))))
+    }
+
+    fn unbox(record: *mut Record<u32>) -> u32 {
+        *unsafe { Box::from_raw(record) }.1.unwrap()
+    }
+
+    /// Publishes a request by hand, as a thread that's still waiting on it would have.
+    fn publish(combining: &Combining<u32>, cell: usize, op: Op, record: *mut Record<u32>) {
+        let request = &combining.requests[cell];
+        request.state.store(CLAIMED, Ordering::Relaxed);
+        request.op.store(OPS.iter().position(|&o| o == op).unwrap() as u32, Ordering::Relaxed);
+        request.record.store(record, Ordering::Relaxed);
+        request.state.store(PENDING, Ordering::Release);
+    }
+
+    #[test]
+    fn combiner_serves_everyone_and_cancels_pairs() {
+        let combining = Combining::new(4);
+        let ring = RefCell::new(vec![]);
+        let apply = |op: Op, pushed: *mut Record<u32>| {
+            if op.is_push() {
+                ring.borrow_mut().push(unbox(pushed));
+                ptr::null_mut()
+            } else {
+                ring.borrow_mut().pop().map_or(ptr::null_mut(), record)
+            }
+        };
+
+        publish(&combining, 0, Op::PopBack, ptr::null_mut());
+        publish(&combining, 1, Op::PushFront, record(1));
+        publish(&combining, 2, Op::PushBack, record(2));
+        let mut attempts = Attempts::new(&crate::Spin);
+        let out = combining.submit(Op::PopFront, ptr::null_mut(), &mut attempts, apply);
+
+        // the back pair cancelled, and so did ours with the front push
+        assert_eq!(1, unbox(out.unwrap()));
+        assert_eq!(DONE, combining.requests[0].state.load(Ordering::Relaxed));
+        assert_eq!(2, unbox(combining.requests[0].record.load(Ordering::Relaxed)));
+        assert!(ring.borrow().is_empty());
+        assert_eq!(4, combining.combined());
+        assert!(combining.hot.load(Ordering::Relaxed));
+
+        for request in &combining.requests[..3] {
+            request.state.store(FREE, Ordering::Relaxed);
+        }
+        // alone, the combiner applies its own request through the ring and cools off
+        let out = combining.submit(Op::PushBack, record(3), &mut attempts, apply);
+        assert_eq!(Some(ptr::null_mut()), out);
+        assert_eq!(vec![3], *ring.borrow());
+        assert!(!combining.is_hot());
+        assert!(combining.is_idle());
+    }
+
+    #[test]
+    fn full_cells_turn_requests_away() {
+        let combining = Combining::<u32>::new(1);
+        publish(&combining, 0, Op::PopBack, ptr::null_mut());
+        let mut attempts = Attempts::new(&crate::Spin);
+        assert_eq!(None, combining.submit(Op::PopFront, ptr::null_mut(), &mut attempts, |_, r| r));
+
+        let off = Combining::<u32>::new(0);
+        assert!(!off.is_hot());
+        assert_eq!(None, off.submit(Op::PopFront, ptr::null_mut(), &mut attempts, |_, r| r));
+    }
+}
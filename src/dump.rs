@@ -0,0 +1,149 @@
+//! A plain-text snapshot of a [`Joque`]'s internals, for bug reports; see [`Joque::dump`].
+
+use core::fmt;
+
+use crate::sync::*;
+use crate::{BITS, Backoff, Joque, LEFTMASK, MARK, RecordAllocator, demux, generation, occupancy};
+
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> Joque<T, A, B> {
+    /// Writes the extents, the claim cursor, every ring slot, and every backing record to
+    /// `w`. Each word is read once, on its own, so with operations in flight the lines can
+    /// disagree with each other; dump a quiescent deque for a consistent picture.
+    ///
+    /// The format is line-oriented and only ever grows new lines at the end of a section, so
+    /// tooling written against one version keeps reading the next:
+    ///
+    /// ```text
+    /// joque dump v1
+    /// width 7 capacity 6 len 2
+    /// extents left 2 right 5
+    /// cursor 3
+    /// slots
+    ///   0 tag 0 record 0
+    ///   3 tag 1 record 2 live
+    ///   ...
+    /// records
+    ///   0 null 0x5581d0a3c9f0
+    ///   2 claimed 0x5581d0a3cab0
+    ///   3 free 0x5581d0a3cad0
+    ///   ...
+    /// ```
+    ///
+    /// Slots are listed in ring order with their op tag (the slot's own generation) and
+    /// backing index, marked `live` when they sit strictly between the extents and `held`
+    /// while an operation has reserved them but not yet filled or drained them. Records
+    /// are listed by backing index with their claim bit and address; the addresses are the
+    /// same ones the `trace` feature logs for record swaps. What a record holds isn't shown:
+    /// another thread may free it at any moment, and [`Joque::validate`] is the place to
+    /// check that.
+    pub fn dump(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        let (left, right) = demux(self.leftright.load(Ordering::Acquire));
+        writeln!(w, "joque dump v1")?;
+        writeln!(
+            w,
+            "width {} capacity {} len {}",
+            self.capacity,
+            self.capacity(),
+            occupancy(left, right, self.capacity)
+        )?;
+        writeln!(w, "extents left {left} right {right}")?;
+        writeln!(w, "cursor {}", self.idx.load(Ordering::Relaxed))?;
+
+        writeln!(w, "slots")?;
+        // how far round from `left` a slot is; live ones are strictly short of `right`
+        let past_left = |slot: u32| (slot + self.capacity - left) % self.capacity;
+        for (slot, word) in self.deque.iter().enumerate() {
+            let val = word.load(Ordering::Acquire);
+            write!(w, "  {slot} tag {} record {}", generation(val), val & LEFTMASK)?;
+            if (1..past_left(right)).contains(&past_left(slot as u32)) {
+                write!(w, " live")?;
+            }
+            if val & MARK != 0 {
+                write!(w, " held")?;
+            }
+            writeln!(w)?;
+        }
+
+        writeln!(w, "records")?;
+        for (record, rj) in self.backing.iter().enumerate() {
+            let claimed = self.claimed[record / BITS].load(Ordering::Relaxed) & 1 << (record % BITS) != 0;
+            let state = match (record, claimed) {
+                (0, _) => "null",
+                (_, true) => "claimed",
+                (_, false) => "free",
+            };
+            writeln!(w, "  {record} {state} {:p}", rj.0.load(Ordering::Acquire))?;
+        }
+        Ok(())
+    }
+}
+
+/// [`Joque::dump`] as a `Display`, for panic messages.
+pub(crate) struct Dumped<'a, T: ?Sized, A: RecordAllocator, B: Backoff>(pub(crate) &'a Joque<T, A, B>);
+
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> fmt::Display for Dumped<'_, T, A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.dump(f)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+
+    #[test]
+    fn renders_extents_slots_and_records() {
+        let deque = Joque::new(5);
+        deque.push_back(Box::new(1));
+        deque.push_back(Box::new(2));
+        deque.push_front(Box::new(0));
+        deque.pop_back();
+        let mut out = String::new();
+        deque.dump(&mut out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        // starts at left 2 right 3; two pushes at the back, one at the front, one pop back
+        assert_eq!(
+            ["joque dump v1", "width 5 capacity 4 len 2", "extents left 1 right 4", "cursor 3", "slots"],
+            lines[..5]
+        );
+        let slots: Vec<&str> = lines[5..10].iter().map(|line| line.trim()).collect();
+        assert_eq!(["0 tag 0 record 0", "1 tag 0 record 0", "4 tag 2 record 0"], [slots[0], slots[1], slots[4]]);
+        for (slot, line) in [(2, slots[2]), (3, slots[3])] {
+            assert!(line.starts_with(&format!("{slot} tag 1 record ")) && line.ends_with(" live"), "{line}");
+        }
+
+        assert_eq!("records", lines[10]);
+        let records = &lines[11..];
+        assert_eq!(20, records.len());
+        assert!(records[0].trim().starts_with("0 null 0x"));
+        assert_eq!(2, records.iter().filter(|line| line.contains(" claimed ")).count());
+    }
+
+    #[test]
+    fn live_slots_wrap_round() {
+        let deque = Joque::new(5);
+        for i in 0..6 {
+            deque.push_back(Box::new(i));
+            deque.pop_front();
+        }
+        deque.push_back(Box::new(6));
+        deque.push_back(Box::new(7));
+        let mut out = String::new();
+        deque.dump(&mut out).unwrap();
+        assert!(out.contains("extents left 3 right 1\n"), "{out}");
+        let live: Vec<&str> = out.lines().filter(|line| line.ends_with(" live")).collect();
+        assert_eq!(2, live.len(), "{out}");
+        assert!(live[0].trim().starts_with("0 ") && live[1].trim().starts_with("4 "), "{out}");
+    }
+
+    #[test]
+    fn shows_up_as_a_display() {
+        let deque = Joque::<u32>::new(6);
+        let mut direct = String::new();
+        deque.dump(&mut direct).unwrap();
+        assert_eq!(direct, format!("{}", Dumped(&deque)));
+    }
+}
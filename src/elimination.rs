@@ -0,0 +1,153 @@
+//! Letting a push and a pop on the same end cancel out without touching the ring.
+//!
+//! A `push_front` followed at once by a `pop_front` leaves the deque as it was, so when the
+//! two collide on `leftright` there's no need for either to win the CAS: the push parks its
+//! record in a small exchange array, the pop takes it from there, and both are done. Each
+//! end has its own array, since a push at one end only cancels a pop at the same end.
+//!
+//! Only pushes wait in the array, and only for one snooze of the deque's
+//! [`Backoff`](crate::Backoff); pops take what's on offer and otherwise back off as usual.
+//! A push that meets a pop is admitted even if the ring filled up in the meantime, because
+//! its item never occupies a slot.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::backoff::{Attempts, Backoff};
+use crate::sync::*;
+use crate::{Op, Record};
+
+/// The exchange arrays for both ends, empty unless the deque was built
+/// [`eliminating`](crate::Joque::eliminating).
+pub(crate) struct Elimination<T: ?Sized> {
+    front: Vec<AtomicPtr<Record<T>>>,
+    back: Vec<AtomicPtr<Record<T>>>,
+}
+
+impl<T: ?Sized> Elimination<T> {
+    pub(crate) fn new(width: usize) -> Self {
+        let lane = || (0..width).map(|_| AtomicPtr::new(ptr::null_mut())).collect();
+        Elimination { front: lane(), back: lane() }
+    }
+
+    /// A pop that finds the offer it took marks the cell with this, and only the push
+    /// that made the offer clears it, so a cell can't go from one offer to another while the
+    /// first push is still deciding whether its record got taken. No record lives at the
+    /// dangling address.
+    fn taken() -> *mut Record<T> {
+        ptr::dangling_mut()
+    }
+
+    fn lane(&self, op: Op) -> &[AtomicPtr<Record<T>>] {
+        match op {
+            Op::PushFront | Op::PopFront => &self.front,
+            Op::PushBack | Op::PopBack => &self.back,
+        }
+    }
+
+    /// Offers `record` to a pop at the same end for one snooze of `attempts`. Returns `true`
+    /// when a pop took it, in which case the record is the pop's now; otherwise it's still
+    /// the caller's. Snoozes either way, so this stands in for the backoff after a lost CAS.
+    pub(crate) fn offer<B: Backoff + ?Sized>(
+        &self,
+        op: Op,
+        record: *mut Record<T>,
+        attempts: &mut Attempts<'_, B>,
+    ) -> bool {
+        let lane = self.lane(op);
+        // records are spread over the heap, so their address makes a passable hash
+        let start = (record as usize >> 4) % lane.len().max(1);
+        let cell = (0..lane.len()).map(|i| &lane[(start + i) % lane.len()]).find(|cell| {
+            cell.compare_exchange(ptr::null_mut(), record, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+        });
+        attempts.snooze();
+        let Some(cell) = cell else {
+            return false;
+        };
+        if cell
+            .compare_exchange(record, ptr::null_mut(), Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            return false;
+        }
+        debug_assert_eq!(Self::taken(), cell.load(Ordering::Relaxed));
+        cell.store(ptr::null_mut(), Ordering::Relaxed);
+        true
+    }
+
+    /// Takes a record some push at the same end is offering, if there is one.
+    pub(crate) fn take(&self, op: Op) -> Option<*mut Record<T>> {
+        self.lane(op).iter().find_map(|cell| {
+            let record = cell.load(Ordering::Relaxed);
+            if record.is_null() || record == Self::taken() {
+                return None;
+            }
+            cell.compare_exchange(record, Self::taken(), Ordering::Acquire, Ordering::Relaxed)
+                .ok()
+        })
+    }
+
+    /// Whether any push is offering a record right now.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.front.iter().chain(&self.back).all(|cell| cell.load(Ordering::Relaxed).is_null())
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    use crate::backoff::Backoff;
+
+    fn record(item: u32) -> *mut Record<u32> {
+        Box::into_raw(Box::new((0, Some(Box::new(item)))))
+    }
+
+    fn unbox(record: *mut Record<u32>) -> u32 {
+        *unsafe { Box::from_raw(record) }.1.unwrap()
+    }
+
+    /// Runs a closure in place of the snooze, to play the pop that turns up meanwhile.
+    struct Meanwhile<F>(F);
+
+    impl<F: Fn()> Backoff for Meanwhile<F> {
+        fn snooze(&self, _retries: u32) {
+            (self.0)()
+        }
+    }
+
+    #[test]
+    fn pop_takes_offer_from_same_end() {
+        let array = Elimination::new(4);
+        let took = core::cell::Cell::new(None);
+        let pop = Meanwhile(|| {
+            assert_eq!(None, array.take(Op::PopBack));
+            took.set(array.take(Op::PopFront));
+        });
+
+        let offered = record(7);
+        assert!(array.offer(Op::PushFront, offered, &mut Attempts::new(&pop)));
+        assert_eq!(Some(offered), took.get());
+        assert_eq!(7, unbox(offered));
+        assert!(array.is_idle());
+    }
+
+    #[test]
+    fn unmet_offer_is_withdrawn() {
+        let array = Elimination::new(2);
+        let offered = record(3);
+        assert!(!array.offer(Op::PushBack, offered, &mut Attempts::new(&crate::Spin)));
+        assert!(array.is_idle());
+        assert_eq!(None, array.take(Op::PopBack));
+        assert_eq!(3, unbox(offered));
+
+        // no cells at all is the disabled layer
+        let off = Elimination::new(0);
+        let offered = record(4);
+        assert!(!off.offer(Op::PushBack, offered, &mut Attempts::new(&crate::Spin)));
+        assert_eq!(None, off.take(Op::PopBack));
+        assert_eq!(4, unbox(offered));
+    }
+}
@@ -0,0 +1,460 @@
+//! Deterministic fault injection at every atomic step, for the `fault-injection` feature.
+//!
+//! With the feature on, the atomics behind `mod sync` are swapped for the wrappers in
+//! [`atomic`], which stop at every load, store, read-modify-write, and CAS to ask the
+//! calling thread's [`Faults`] whether something is due there: a yield, a sleep, or, at a
+//! weak CAS, a spurious failure. Whether it is comes from a stream seeded by the plan's
+//! seed and the number the thread entered with, so a thread sees the same faults at the same
+//! steps every run. Single-threaded, that makes a run exactly repeatable, including paths
+//! that otherwise need real contention, like an operation handing itself to the combiner.
+//! Across threads the faults pull the scheduler around enough to make rare interleavings
+//! common, and a failing seed stays failing far more often than not.
+//!
+//! Only weak CASes are ever failed: every caller already has to retry those, whereas a
+//! strong CAS that fails tells its caller something about the value.
+//!
+//! ```ignore
+//! let faults = Faults::seeded(7).yields(4).cas_failures(3);
+//! let _entered = faults.enter(0);
+//! // every atomic step on this thread may now yield or fail
+//! ```
+
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::ops::BitOr;
+use core::time::Duration;
+
+/// Kinds of atomic step a fault can be injected at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sites(u8);
+
+impl Sites {
+    pub const LOADS: Sites = Sites(1);
+    pub const STORES: Sites = Sites(2);
+    /// Swaps and the `fetch_*` family.
+    pub const RMWS: Sites = Sites(4);
+    /// Compare-exchanges, strong or weak.
+    pub const CASES: Sites = Sites(8);
+    pub const ALL: Sites = Sites(15);
+
+    pub fn contains(self, other: Sites) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Sites {
+    type Output = Sites;
+
+    fn bitor(self, other: Sites) -> Sites {
+        Sites(self.0 | other.0)
+    }
+}
+
+/// What to inject, where, and how often. Rates are "one step in n", with 0 for never; a
+/// plan starts out injecting nothing, at every kind of step.
+#[derive(Clone, Debug)]
+pub struct Faults {
+    seed: u64,
+    sites: Sites,
+    yields: u32,
+    sleeps: u32,
+    sleep: Duration,
+    cas_failures: u32,
+}
+
+impl Faults {
+    pub fn seeded(seed: u64) -> Self {
+        Faults { seed, sites: Sites::ALL, yields: 0, sleeps: 0, sleep: Duration::ZERO, cas_failures: 0 }
+    }
+
+    /// Injects only at these kinds of step.
+    pub fn at(mut self, sites: Sites) -> Self {
+        self.sites = sites;
+        self
+    }
+
+    /// Yields to the OS at one step in `one_in`.
+    pub fn yields(mut self, one_in: u32) -> Self {
+        self.yields = one_in;
+        self
+    }
+
+    /// Sleeps for `sleep` at one step in `one_in`.
+    pub fn sleeps(mut self, one_in: u32, sleep: Duration) -> Self {
+        self.sleeps = one_in;
+        self.sleep = sleep;
+        self
+    }
+
+    /// Fails one weak CAS in `one_in` without trying it. Anything under 2 would fail them
+    /// all and never get anywhere.
+    pub fn cas_failures(mut self, one_in: u32) -> Self {
+        assert!(one_in != 1, "failing every CAS livelocks");
+        self.cas_failures = one_in;
+        self
+    }
+
+    /// Injects into the calling thread's atomic steps until the guard drops, drawing from
+    /// the stream for `thread`. Give each thread in a run its own number to have them fault
+    /// at different steps.
+    pub fn enter(&self, thread: u64) -> Entered {
+        let stream = Stream {
+            faults: self.clone(),
+            state: self.seed ^ thread.wrapping_mul(0xd1b5_4a32_d192_ed03),
+            injected: Injected::default(),
+        };
+        let outer = ENTERED.with(|entered| entered.replace(Some(stream)));
+        Entered { outer, _not_send: PhantomData }
+    }
+}
+
+/// How much got injected into one thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Injected {
+    /// Atomic steps at the plan's sites, faulted or not.
+    pub steps: u64,
+    pub yields: u64,
+    pub sleeps: u64,
+    pub cas_failures: u64,
+}
+
+/// A thread's faults, in effect until dropped; see [`Faults::enter`].
+pub struct Entered {
+    outer: Option<Stream>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Entered {
+    pub fn injected(&self) -> Injected {
+        ENTERED.with(|entered| entered.borrow().as_ref().map_or_else(Injected::default, |s| s.injected))
+    }
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let _ = ENTERED.try_with(|entered| entered.replace(self.outer.take()));
+    }
+}
+
+struct Stream {
+    faults: Faults,
+    state: u64,
+    injected: Injected,
+}
+
+std::thread_local! {
+    static ENTERED: RefCell<Option<Stream>> = const { RefCell::new(None) };
+}
+
+/// One atomic step at `site`. Returns whether a weak CAS here should fail, which is never
+/// unless `weak`.
+pub(crate) fn step(site: Sites, weak: bool) -> bool {
+    let due = ENTERED.try_with(|entered| {
+        let mut entered = entered.borrow_mut();
+        let stream = entered.as_mut()?;
+        if !stream.faults.sites.contains(site) {
+            return None;
+        }
+        // splitmix64, one draw per step whatever comes of it, so the streams stay in step
+        stream.state = stream.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut roll = stream.state;
+        roll = (roll ^ (roll >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        roll = (roll ^ (roll >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        roll ^= roll >> 31;
+        let hit = |bits: u64, one_in: u32| one_in != 0 && (bits & 0x1f_ffff).is_multiple_of(one_in as u64);
+
+        let faults = &stream.faults;
+        let injected = &mut stream.injected;
+        injected.steps += 1;
+        let fail = weak && hit(roll >> 42, faults.cas_failures);
+        let sleep = hit(roll >> 21, faults.sleeps).then_some(faults.sleep);
+        let yielding = hit(roll, faults.yields);
+        injected.cas_failures += fail as u64;
+        injected.sleeps += sleep.is_some() as u64;
+        injected.yields += yielding as u64;
+        Some((fail, sleep, yielding))
+    });
+    // act outside the borrow; nothing here touches our atomics, but better safe
+    let Ok(Some((fail, sleep, yielding))) = due else {
+        return false;
+    };
+    if let Some(sleep) = sleep {
+        std::thread::sleep(sleep);
+    }
+    if yielding {
+        std::thread::yield_now();
+    }
+    fail
+}
+
+/// Drop-in wrappers for the std atomics that `mod sync` hands out with the feature on.
+#[allow(dead_code)]
+pub(crate) mod atomic {
+    use core::sync::atomic as std;
+    use core::sync::atomic::Ordering;
+
+    use super::{Sites, step};
+
+    macro_rules! faulty {
+        ($name:ident, $val:ty $(, $rmw:ident)*) => {
+            pub(crate) struct $name(std::$name);
+
+            impl $name {
+                pub(crate) const fn new(val: $val) -> Self {
+                    $name(std::$name::new(val))
+                }
+
+                pub(crate) fn into_inner(self) -> $val {
+                    self.0.into_inner()
+                }
+
+                pub(crate) fn load(&self, order: Ordering) -> $val {
+                    step(Sites::LOADS, false);
+                    self.0.load(order)
+                }
+
+                pub(crate) fn store(&self, val: $val, order: Ordering) {
+                    step(Sites::STORES, false);
+                    self.0.store(val, order)
+                }
+
+                pub(crate) fn swap(&self, val: $val, order: Ordering) -> $val {
+                    step(Sites::RMWS, false);
+                    self.0.swap(val, order)
+                }
+
+                pub(crate) fn compare_exchange(
+                    &self,
+                    current: $val,
+                    new: $val,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$val, $val> {
+                    step(Sites::CASES, false);
+                    self.0.compare_exchange(current, new, success, failure)
+                }
+
+                pub(crate) fn compare_exchange_weak(
+                    &self,
+                    current: $val,
+                    new: $val,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$val, $val> {
+                    if step(Sites::CASES, true) {
+                        return Err(self.0.load(failure));
+                    }
+                    self.0.compare_exchange_weak(current, new, success, failure)
+                }
+
+                pub(crate) fn fetch_update<F: FnMut($val) -> Option<$val>>(
+                    &self,
+                    set: Ordering,
+                    fetch: Ordering,
+                    f: F,
+                ) -> Result<$val, $val> {
+                    step(Sites::RMWS, false);
+                    self.0.fetch_update(set, fetch, f)
+                }
+
+                $(
+                    pub(crate) fn $rmw(&self, val: $val, order: Ordering) -> $val {
+                        step(Sites::RMWS, false);
+                        self.0.$rmw(val, order)
+                    }
+                )*
+            }
+        };
+    }
+
+    faulty!(AtomicBool, bool, fetch_or, fetch_and);
+    faulty!(AtomicU32, u32, fetch_add, fetch_sub, fetch_or, fetch_and);
+    faulty!(AtomicUsize, usize, fetch_add, fetch_sub, fetch_or, fetch_and);
+    #[cfg(target_has_atomic = "64")]
+    faulty!(AtomicU64, u64, fetch_add, fetch_sub, fetch_or, fetch_and);
+
+    pub(crate) struct AtomicPtr<T>(std::AtomicPtr<T>);
+
+    impl<T> AtomicPtr<T> {
+        pub(crate) const fn new(ptr: *mut T) -> Self {
+            AtomicPtr(std::AtomicPtr::new(ptr))
+        }
+
+        pub(crate) fn into_inner(self) -> *mut T {
+            self.0.into_inner()
+        }
+
+        pub(crate) fn load(&self, order: Ordering) -> *mut T {
+            step(Sites::LOADS, false);
+            self.0.load(order)
+        }
+
+        pub(crate) fn store(&self, ptr: *mut T, order: Ordering) {
+            step(Sites::STORES, false);
+            self.0.store(ptr, order)
+        }
+
+        pub(crate) fn swap(&self, ptr: *mut T, order: Ordering) -> *mut T {
+            step(Sites::RMWS, false);
+            self.0.swap(ptr, order)
+        }
+
+        pub(crate) fn compare_exchange(
+            &self,
+            current: *mut T,
+            new: *mut T,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<*mut T, *mut T> {
+            step(Sites::CASES, false);
+            self.0.compare_exchange(current, new, success, failure)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use crate::Joque;
+    use crate::history::{self, Clock, Recorder};
+
+    /// A fixed single-threaded workload, returning everything it popped.
+    fn workload(deque: &Joque<u32>) -> Vec<Option<u32>> {
+        let mut popped = vec![];
+        for i in 0..64 {
+            if i % 3 == 0 {
+                deque.push_front(Box::new(i));
+            } else {
+                deque.push_back(Box::new(i));
+            }
+            if i % 4 == 3 {
+                popped.push(deque.pop_front().map(|item| *item));
+                popped.push(deque.pop_back().map(|item| *item));
+            }
+        }
+        popped
+    }
+
+    #[test]
+    fn same_seed_same_faults() {
+        let run = |seed| {
+            let entered = Faults::seeded(seed).yields(5).cas_failures(3).enter(0);
+            let popped = workload(&Joque::new(64));
+            (popped, entered.injected())
+        };
+        let (popped, injected) = run(11);
+        assert!(injected.yields > 0 && injected.cas_failures > 0, "{injected:?}");
+        assert_eq!((popped.clone(), injected), run(11));
+        assert_ne!(injected, run(12).1);
+
+        // and the faults changed nothing a caller can see
+        assert_eq!(popped, workload(&Joque::new(64)));
+    }
+
+    #[test]
+    fn sites_pick_where_faults_land() {
+        let entered = Faults::seeded(3).at(Sites::LOADS | Sites::STORES).cas_failures(2).enter(0);
+        workload(&Joque::new(64));
+        assert!(entered.injected().steps > 0);
+        assert_eq!(0, entered.injected().cas_failures);
+        drop(entered);
+
+        let entered = Faults::seeded(3).at(Sites::CASES).cas_failures(2).enter(0);
+        workload(&Joque::new(64));
+        assert!(entered.injected().cas_failures > 0);
+    }
+
+    #[test]
+    fn failed_cases_reach_the_combiner_alone() {
+        // losing enough CASes in a row hands an operation to the combiner, with nobody else
+        // around to make it happen for real
+        let _entered = Faults::seeded(1).at(Sites::CASES).cas_failures(2).enter(0);
+        let deque = Joque::new(64).combining(2);
+        let mut spec = VecDeque::new();
+        for i in 0..120u32 {
+            if i % 3 == 2 {
+                assert_eq!(spec.pop_front(), deque.pop_front().map(|item| *item));
+            } else {
+                spec.push_back(i);
+                deque.push_back(Box::new(i));
+            }
+        }
+        assert!(deque.retries().combined > 0);
+        assert_eq!(spec.len(), deque.len());
+    }
+
+    /// Four faulted threads pushing and popping at both ends, returning their history and
+    /// whatever was left in the deque.
+    fn faulted_run(faults: &Faults) -> Vec<history::Entry> {
+        let clock = Clock::default();
+        let deque = Joque::new(16);
+        let history = std::thread::scope(|s| {
+            let ths: Vec<_> = (0..4u32)
+                .map(|t| {
+                    let (deque, clock) = (&deque, &clock);
+                    s.spawn(move || {
+                        let _entered = faults.enter(t as u64);
+                        let mut rec = Recorder::new(clock);
+                        for i in 0..24 {
+                            match (t + i) % 4 {
+                                0 => rec.push_front(deque, t * 100 + i),
+                                1 => rec.push_back(deque, t * 100 + i),
+                                2 => {
+                                    rec.pop_front(deque);
+                                }
+                                _ => {
+                                    rec.pop_back(deque);
+                                }
+                            }
+                        }
+                        rec.log
+                    })
+                })
+                .collect();
+            ths.into_iter().flat_map(|th| th.join().unwrap()).collect::<Vec<_>>()
+        });
+        // what's left comes out in the history too, after everything the threads did
+        let mut rec = Recorder::new(&clock);
+        while rec.pop_front(&deque).is_some() {}
+        history.into_iter().chain(rec.log).collect()
+    }
+
+    fn faults(seed: u64) -> Faults {
+        Faults::seeded(seed).yields(6).sleeps(97, Duration::from_micros(20)).cas_failures(3)
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn faulted_threads_lose_nothing() {
+        for seed in 0..16 {
+            let history = faulted_run(&faults(seed));
+            let (mut pushed, mut out) = (vec![], vec![]);
+            for entry in &history {
+                match entry.call {
+                    history::Call::PushFront(item) | history::Call::PushBack(item) => pushed.push(item),
+                    _ => out.extend(entry.ret),
+                }
+            }
+            pushed.sort_unstable();
+            out.sort_unstable();
+            assert_eq!(pushed, out, "seed {seed}: items lost or duplicated");
+        }
+    }
+
+    /// Every operation is bound to the slot generation it marked, so however the faults
+    /// stall a thread between reserving a slot and finishing with it, no other operation
+    /// can take or overwrite its item.
+    #[cfg(not(miri))]
+    #[test]
+    fn faulted_threads_stay_linearizable() {
+        for seed in 0..16 {
+            let history = faulted_run(&faults(seed));
+            if let Err(unlinearizable) = history::check(&history) {
+                panic!("seed {seed}: {unlinearizable:?}");
+            }
+        }
+    }
+}
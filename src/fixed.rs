@@ -0,0 +1,328 @@
+//! A deque that never touches the heap, for firmware and other no_std targets.
+//!
+//! [`StaticJoque`] runs the same reserve-then-fill protocol as [`Joque`](crate::Joque), but
+//! its ring and backing records are inline arrays sized by a const parameter, and an item
+//! sits in its record by value instead of behind a `Box`. `new` is a `const fn`, so the
+//! whole deque can be placed in a `static`.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+use crate::backoff::{Attempts, Yield};
+use crate::pad::CachePadded;
+use crate::sync::*;
+use crate::{LEFTMASK, Op, Reserved, Ring, demux, generation, mux, next_gen, occupancy, reserve};
+
+/// A backing record: the op tag an item was pushed under and the item itself. Only the
+/// thread holding the record's `claimed` flag touches it.
+struct StaticRecord<T>(UnsafeCell<(Tag, MaybeUninit<T>)>);
+
+impl<T> StaticRecord<T> {
+    const fn new() -> Self {
+        StaticRecord(UnsafeCell::new((0, MaybeUninit::uninit())))
+    }
+}
+
+/// StaticJoque is a [`Joque`](crate::Joque) with `N` ring slots and `N` backing records
+/// stored inline.
+///
+/// A slot word holds `generation << HALF | record + 1`, zero meaning empty as before. A push
+/// claims a free record, writes its item there and publishes the record in its slot; the
+/// pop that clears the slot reads the item back out and frees the record.
+///
+/// Declared as `static EVENTS: StaticJoque<Event, 64> = StaticJoque::new();` it's usable
+/// from every thread with no setup. Don't share one with an interrupt handler: a push or
+/// pop interrupted between reserving and finishing leaves the handler spinning on it.
+pub struct StaticJoque<T, const N: usize> {
+    deque: [AtomicWord; N], // left packed whatever `padded-slots` says; RAM is dearer here
+    leftright: CachePadded<AtomicWord>,
+    backing: [StaticRecord<T>; N],
+    claimed: [AtomicBool; N], // set while a push or a live slot owns the record
+    idx: CachePadded<AtomicU32>, // where the next claim starts scanning `claimed`
+}
+
+// Items only ever move through the deque, never get shared by it.
+unsafe impl<T: Send, const N: usize> Sync for StaticJoque<T, N> {}
+
+impl<T, const N: usize> StaticJoque<T, N> {
+    // checked when `new` is instantiated, so a bad `N` fails the build rather than the boot
+    const FITS: () = {
+        assert!(N >= 5, "let's not");
+        assert!(N as u128 <= LEFTMASK as u128, "too wide for the slot halves");
+    };
+
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS;
+        let left = N as u32 / 2;
+        StaticJoque {
+            deque: [const { AtomicWord::new(0) }; N],
+            leftright: CachePadded::new(AtomicWord::new(mux(left, left + 1))),
+            backing: [const { StaticRecord::new() }; N],
+            claimed: [const { AtomicBool::new(false) }; N],
+            idx: CachePadded::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Loom's atomics can't be built in a const context.
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS;
+        let left = N as u32 / 2;
+        StaticJoque {
+            deque: core::array::from_fn(|_| AtomicWord::new(0)),
+            leftright: CachePadded::new(AtomicWord::new(mux(left, left + 1))),
+            backing: core::array::from_fn(|_| StaticRecord::new()),
+            claimed: core::array::from_fn(|_| AtomicBool::new(false)),
+            idx: CachePadded::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Pushes onto the front, waiting for a pop to make room if the ring is full.
+    pub fn push_front(&self, mut item: T) {
+        let mut attempts = Attempts::new(&Yield);
+        while let Err(refused) = self.try_push_front(item) {
+            item = refused;
+            attempts.snooze();
+        }
+    }
+
+    /// Pushes onto the front, handing `item` back if all slots are taken.
+    pub fn try_push_front(&self, item: T) -> Result<(), T> {
+        let Some(reserved) = reserve(self, Op::PushFront, &mut Attempts::new(&Yield)) else {
+            return Err(item);
+        };
+        self.fill(reserved, item);
+        Ok(())
+    }
+
+    pub fn pop_front(&self) -> Option<T> {
+        let reserved = reserve(self, Op::PopFront, &mut Attempts::new(&Yield))?;
+        Some(self.drain(reserved))
+    }
+
+    /// Pushes onto the back, waiting for a pop to make room if the ring is full.
+    pub fn push_back(&self, mut item: T) {
+        let mut attempts = Attempts::new(&Yield);
+        while let Err(refused) = self.try_push_back(item) {
+            item = refused;
+            attempts.snooze();
+        }
+    }
+
+    /// Pushes onto the back, handing `item` back if all slots are taken.
+    pub fn try_push_back(&self, item: T) -> Result<(), T> {
+        let Some(reserved) = reserve(self, Op::PushBack, &mut Attempts::new(&Yield)) else {
+            return Err(item);
+        };
+        self.fill(reserved, item);
+        Ok(())
+    }
+
+    pub fn pop_back(&self) -> Option<T> {
+        let reserved = reserve(self, Op::PopBack, &mut Attempts::new(&Yield))?;
+        Some(self.drain(reserved))
+    }
+
+    /// Parks `item` in a free record and publishes it into the slot reserved for it.
+    fn fill(&self, Reserved { slot, val }: Reserved, item: T) {
+        let record = self.claim_backing();
+        let tag = next_gen(val);
+        // the claim makes the record ours until a pop reads it back out
+        unsafe { *self.backing[record].0.get() = (tag as Tag, MaybeUninit::new(item)) };
+        self.publish(slot, val, (tag << HALF) | (record as Word + 1));
+    }
+
+    /// Takes the item out of the slot reserved for a pop.
+    fn drain(&self, Reserved { slot, val: old_one }: Reserved) -> T {
+        self.publish(slot, old_one, next_gen(old_one) << HALF);
+        let record = (old_one & LEFTMASK) as usize - 1;
+        // clearing the slot made us the only one who can reach the record
+        let (tag, item) = unsafe { self.backing[record].0.get().read() };
+        self.claimed[record].store(false, Ordering::Release);
+        debug_assert_eq!(generation(old_one), tag as Word, "record outlived its slot");
+        unsafe { item.assume_init() }
+    }
+
+    /// Claims a free backing record. There are as many records as ring slots, so one
+    /// only runs short while pops are still reading theirs out.
+    fn claim_backing(&self) -> usize {
+        let start = self.idx.fetch_add(1, Ordering::Relaxed) as usize;
+        loop {
+            for i in 0..N {
+                let record = (start + i) % N;
+                if !self.claimed[record].load(Ordering::Relaxed)
+                    && !self.claimed[record].swap(true, Ordering::Acquire)
+                {
+                    return record;
+                }
+            }
+            relax();
+        }
+    }
+
+    /// See [`Joque::len`](crate::Joque::len).
+    pub fn len(&self) -> usize {
+        let (left, right) = demux(self.leftright.load(Ordering::Acquire));
+        occupancy(left, right, N as u32)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len()
+    }
+}
+
+impl<T, const N: usize> Default for StaticJoque<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Ring for StaticJoque<T, N> {
+    fn extents(&self) -> &AtomicWord {
+        &self.leftright
+    }
+
+    fn slot(&self, slot: u32) -> &AtomicWord {
+        &self.deque[slot as usize]
+    }
+
+    fn width(&self) -> u32 {
+        N as u32
+    }
+}
+
+impl<T, const N: usize> Drop for StaticJoque<T, N> {
+    fn drop(&mut self) {
+        /// Keeps popping if an item's destructor panics part way through.
+        struct Unwinding<'a, T, const N: usize>(&'a mut StaticJoque<T, N>);
+
+        impl<T, const N: usize> Drop for Unwinding<'_, T, N> {
+            fn drop(&mut self) {
+                while self.0.pop_front().is_some() {}
+            }
+        }
+
+        let guard = Unwinding(self);
+        while guard.0.pop_front().is_some() {}
+        core::mem::forget(guard);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::StaticJoque;
+
+    #[cfg(not(loom))]
+    #[test]
+    fn static_basic() {
+        static DEQUE: StaticJoque<&str, 8> = StaticJoque::new();
+
+        DEQUE.push_front("squirp");
+        DEQUE.push_front("squirpy");
+        DEQUE.push_back("squirpiest");
+
+        assert_eq!(3, DEQUE.len());
+        assert_eq!(Some("squirpy"), DEQUE.pop_front());
+        assert_eq!(Some("squirpiest"), DEQUE.pop_back());
+        assert_eq!(Some("squirp"), DEQUE.pop_back());
+        assert_eq!(None, DEQUE.pop_front());
+        assert!(DEQUE.is_empty());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn static_wrap_and_full() {
+        let deque = StaticJoque::<u64, 6>::new();
+        for i in 0..100 {
+            deque.push_front(i);
+            assert_eq!(Some(i), deque.pop_back());
+        }
+
+        for i in 0..deque.capacity() as u64 {
+            deque.push_back(i);
+        }
+        assert_eq!(Err(9), deque.try_push_front(9));
+        assert_eq!(0, deque.remaining());
+        assert_eq!(Some(0), deque.pop_front());
+        assert!(deque.try_push_front(9).is_ok());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn static_drops_queued_items() {
+        use std::rc::Rc;
+
+        let item = Rc::new(());
+        let deque = StaticJoque::<_, 8>::new();
+        for _ in 0..5 {
+            deque.push_back(item.clone());
+        }
+        drop(deque.pop_front());
+        assert_eq!(5, Rc::strong_count(&item));
+        drop(deque);
+        assert_eq!(1, Rc::strong_count(&item));
+    }
+
+    #[cfg(all(not(loom), not(miri)))]
+    #[test]
+    fn static_interleaved() {
+        static DEQUE: StaticJoque<u32, 1024> = StaticJoque::new();
+
+        let THREAD_COUNT = 16u32;
+        let ths: Vec<_> = (0..THREAD_COUNT)
+            .map(|idx| {
+                std::thread::spawn(move || {
+                    let mut popped = vec![];
+                    for i in 0..32 {
+                        DEQUE.push_back(idx * 1000 + i);
+                        DEQUE.push_front(idx * 1000 + i);
+                        popped.push(DEQUE.pop_back().unwrap());
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        // every value went in twice; between the pops and what's left, it comes out twice
+        let mut seen: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        seen.extend(std::iter::from_fn(|| DEQUE.pop_front()));
+        seen.sort();
+        let mut expected: Vec<_> = (0..THREAD_COUNT)
+            .flat_map(|idx| (0..32).flat_map(move |i| [idx * 1000 + i; 2]))
+            .collect();
+        expected.sort();
+        assert_eq!(expected, seen);
+    }
+
+    #[cfg(all(loom, not(miri)))]
+    #[test]
+    fn static_loom_push_pop() {
+        loom::model(|| {
+            let deque = loom::sync::Arc::new(StaticJoque::<u32, 5>::new());
+            let other = deque.clone();
+            let th = loom::thread::spawn(move || {
+                other.push_front(1);
+                other.pop_back()
+            });
+            deque.push_back(2);
+            let mine = deque.pop_front();
+            let theirs = th.join().unwrap();
+            let mut seen: Vec<_> = [mine, theirs].into_iter().flatten().collect();
+            seen.extend(std::iter::from_fn(|| deque.pop_front()));
+            seen.sort();
+            assert_eq!(vec![1, 2], seen);
+        });
+    }
+}
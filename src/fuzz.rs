@@ -0,0 +1,133 @@
+//! An entry point for fuzzers: bytes in, a checked multi-threaded run out.
+//!
+//! [`run_ops`] reads its input as a small script. The first byte shapes the deque and the
+//! crowd working on it; every byte after that is one operation for one thread:
+//!
+//! ```text
+//! shape:  bit 7 combining | bit 6 eliminating | bits 5-4 threads - 1 | bits 3-0 width - 5
+//! op:     bits 7-2 thread (mod threads) | bits 1-0 push front, push back, pop front, pop back
+//! ```
+//!
+//! Each pushed item is numbered by its op's position in the input, so every item is
+//! distinct. Once all threads finish, the deque has to [validate](crate::Joque::validate),
+//! the run has to account for every item exactly once, popped or still in the deque, and
+//! the recorded history has to linearize against a sequential deque of the same capacity.
+//! Any violation panics, which is what a fuzzer watches for. A libFuzzer target is a
+//! one-liner around it (see `fuzz/` in the repository), and a crash input can be replayed
+//! by passing the same bytes from a plain test.
+
+use std::collections::VecDeque;
+use std::sync::Barrier;
+use std::thread;
+use std::vec::Vec;
+
+use crate::history::{self, Call, Clock, Recorder};
+use crate::Joque;
+
+/// Ops past this many are ignored, to keep the linearizability search quick.
+pub const MAX_OPS: usize = 256;
+
+/// Decodes `data` into a script, runs it, and panics if the deque lost, duplicated, or
+/// reordered anything, turned a push away while it had room, or was left structurally
+/// inconsistent.
+pub fn run_ops(data: &[u8]) {
+    let Some((&shape, ops)) = data.split_first() else {
+        return;
+    };
+    let width = 5 + (shape & 0x0f) as u32;
+    let threads = 1 + (shape >> 4 & 0b11) as usize;
+    let mut deque = Joque::new(width);
+    if shape & 0x40 != 0 {
+        deque = deque.eliminating(2);
+    }
+    if shape & 0x80 != 0 {
+        deque = deque.combining(threads);
+    }
+
+    let mut scripts = vec![Vec::new(); threads];
+    for (i, &op) in ops.iter().take(MAX_OPS).enumerate() {
+        let call = match op & 0b11 {
+            0 => Call::PushFront(i as u32),
+            1 => Call::PushBack(i as u32),
+            2 => Call::PopFront,
+            _ => Call::PopBack,
+        };
+        scripts[(op >> 2) as usize % threads].push(call);
+    }
+
+    let clock = Clock::default();
+    let start = Barrier::new(threads);
+    let history: Vec<_> = thread::scope(|s| {
+        let ths: Vec<_> = scripts
+            .iter()
+            .map(|script| {
+                let (deque, clock, start) = (&deque, &clock, &start);
+                s.spawn(move || {
+                    let mut rec = Recorder::new(clock);
+                    start.wait();
+                    for &call in script {
+                        rec.call(deque, call);
+                    }
+                    rec.log
+                })
+            })
+            .collect();
+        ths.into_iter().flat_map(|th| th.join().unwrap()).collect()
+    });
+
+    let report = deque.validate();
+    assert!(report.is_ok(), "{report}");
+
+    // conservation: every admitted push comes out once, by a pop or from what's left
+    let left = deque.len();
+    let mut out: Vec<u32> = history
+        .iter()
+        .filter(|entry| matches!(entry.call, Call::PopFront | Call::PopBack))
+        .filter_map(|entry| entry.ret)
+        .collect();
+    let popped = out.len();
+    while let Some(item) = deque.pop_front() {
+        out.push(*item);
+    }
+    assert_eq!(left, out.len() - popped, "len disagrees with what's left");
+    let mut admitted: Vec<u32> = history
+        .iter()
+        .filter_map(|entry| match entry.call {
+            Call::PushFront(item) | Call::PushBack(item) if entry.ret.is_none() => Some(item),
+            _ => None,
+        })
+        .collect();
+    admitted.sort_unstable();
+    out.sort_unstable();
+    assert_eq!(admitted, out, "items lost or duplicated");
+
+    // ordering: some sequential deque could have answered every call as this one did
+    if let Err(unlinearizable) = history::check_bounded(deque.capacity(), VecDeque::new(), &history) {
+        panic!("{unlinearizable:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Rng;
+
+    #[test]
+    fn short_and_empty_inputs() {
+        run_ops(&[]);
+        run_ops(&[0]);
+        // one thread fills a width-5 ring from both ends, overflows it, then drains it
+        run_ops(&[0x00, 0, 1, 0, 1, 0, 1, 2, 3, 2, 3, 2]);
+    }
+
+    #[test]
+    fn every_shape_under_random_scripts() {
+        let mut rng = Rng::new(43);
+        for shape in 0..=u8::MAX {
+            let len = if cfg!(miri) { 8 } else { 64 };
+            let mut data = vec![shape];
+            data.extend((0..len).map(|_| rng.next_u64() as u8));
+            run_ops(&data);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Recording what concurrent callers saw, and checking it could have happened.
+//!
+//! Each thread wraps its calls in a [`Recorder`], which stamps every invocation and
+//! response from one shared [`Clock`]. The merged history is linearizable if every operation
+//! can be given a single instant, somewhere between its two stamps, such that playing the
+//! operations in that order on a `VecDeque` gives every pop the value it actually got.
+//! [`check`] looks for such an order with Wing and Gong's search, as refined by Lowe:
+//! linearize any call that's pending, back out on reaching a response that hasn't been, and
+//! remember every (linearized set, deque contents) pair already explored. Contents whose
+//! items could no longer leave in the order their pops allow are given up on at once; see
+//! [`Exits`].
+//!
+//! Besides the tests, the [`fuzz`](crate::fuzz) entry point checks its runs this way, so it's
+//! built with that feature too.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::vec::Vec;
+
+use crate::Joque;
+
+/// What was asked of the deque.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Call {
+    PushFront(u32),
+    PushBack(u32),
+    PopFront,
+    PopBack,
+}
+
+/// One completed operation: the call, what it got back, and when it started and finished. A
+/// pop gets back its item; a push gets back its own item if the deque turned it away.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Entry {
+    pub(crate) call: Call,
+    pub(crate) ret: Option<u32>,
+    pub(crate) invoked: u64,
+    pub(crate) returned: u64,
+}
+
+/// A global order over every invocation and response in a run.
+#[derive(Default)]
+pub(crate) struct Clock(AtomicU64);
+
+impl Clock {
+    fn tick(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// One thread's side of a history.
+pub(crate) struct Recorder<'a> {
+    clock: &'a Clock,
+    pub(crate) log: Vec<Entry>,
+}
+
+impl<'a> Recorder<'a> {
+    pub(crate) fn new(clock: &'a Clock) -> Self {
+        Recorder { clock, log: Vec::new() }
+    }
+
+    /// Runs `run` as `call`, logging it along with what it returned.
+    pub(crate) fn record(&mut self, call: Call, run: impl FnOnce() -> Option<u32>) -> Option<u32> {
+        let invoked = self.clock.tick();
+        let ret = run();
+        let returned = self.clock.tick();
+        self.log.push(Entry { call, ret, invoked, returned });
+        ret
+    }
+
+    /// Runs `call` on `deque`, trying pushes rather than insisting on them.
+    pub(crate) fn call(&mut self, deque: &Joque<u32>, call: Call) -> Option<u32> {
+        self.record(call, || match call {
+            Call::PushFront(item) => deque.try_push_front(Box::new(item)).err().map(|item| *item),
+            Call::PushBack(item) => deque.try_push_back(Box::new(item)).err().map(|item| *item),
+            Call::PopFront => deque.pop_front().map(|item| *item),
+            Call::PopBack => deque.pop_back().map(|item| *item),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn push_front(&mut self, deque: &Joque<u32>, item: u32) {
+        self.record(Call::PushFront(item), || {
+            deque.push_front(Box::new(item));
+            None
+        });
+    }
+
+    #[cfg(test)]
+    pub(crate) fn push_back(&mut self, deque: &Joque<u32>, item: u32) {
+        self.record(Call::PushBack(item), || {
+            deque.push_back(Box::new(item));
+            None
+        });
+    }
+
+    #[cfg(test)]
+    pub(crate) fn pop_front(&mut self, deque: &Joque<u32>) -> Option<u32> {
+        self.record(Call::PopFront, || deque.pop_front().map(|item| *item))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn pop_back(&mut self, deque: &Joque<u32>) -> Option<u32> {
+        self.record(Call::PopBack, || deque.pop_back().map(|item| *item))
+    }
+}
+
+/// Plays `entry` against the sequential spec, returning the deque it leaves behind if it
+/// would have returned what it did. A push may only be turned away once `capacity` items are
+/// in, but one that got in past it is let be, since an eliminated push never needed a slot.
+fn step(state: &VecDeque<u32>, capacity: usize, entry: &Entry) -> Option<VecDeque<u32>> {
+    let mut next = state.clone();
+    let got = match entry.call {
+        Call::PushFront(item) | Call::PushBack(item) if entry.ret.is_some() => {
+            return (entry.ret == Some(item) && state.len() >= capacity).then_some(next);
+        }
+        Call::PushFront(item) => {
+            next.push_front(item);
+            None
+        }
+        Call::PushBack(item) => {
+            next.push_back(item);
+            None
+        }
+        Call::PopFront => next.pop_front(),
+        Call::PopBack => next.pop_back(),
+    };
+    (got == entry.ret).then_some(next)
+}
+
+/// When each item in a history can leave the deque, for dropping hopeless contents early.
+///
+/// Items never pass each other, so an item can only be popped from the front once everything
+/// in front of it has been popped, and from the back once everything behind it has. Contents
+/// that put an item ahead of one whose pop was over before the item's own pop began can't
+/// be played out, however the rest is ordered. A long-running push can be linearized almost
+/// anywhere, and without this the search tries every one of those places for each of them.
+struct Exits(HashMap<u32, Exit>);
+
+#[derive(Clone, Copy, Default)]
+struct Exit {
+    /// The first invocation of a pop that got the item.
+    earliest: u64,
+    /// The last response of a pop from each end that got it.
+    front: Option<u64>,
+    back: Option<u64>,
+}
+
+impl Exits {
+    /// How many other invocations and responses a push has to span to be worth the scans.
+    const LONG: usize = 32;
+
+    /// `None` unless some push is [`LONG`](Self::LONG): a short one can only go a few places
+    /// anyway. Also `None` unless every item is pushed once, since a value that's in twice
+    /// could be popped by either copy's pop, or stay put for good, and then there's nothing
+    /// to go on.
+    fn new(initial: &VecDeque<u32>, entries: &[Entry], span: &[usize]) -> Option<Self> {
+        let long = |(entry, &span): (&Entry, &usize)| {
+            span >= Self::LONG && matches!(entry.call, Call::PushFront(_) | Call::PushBack(_))
+        };
+        if !entries.iter().zip(span).any(long) {
+            return None;
+        }
+        let mut pushed = HashSet::new();
+        let mut exits = HashMap::<u32, Exit>::new();
+        for item in initial.iter().copied().chain(entries.iter().filter_map(|entry| match entry.call {
+            Call::PushFront(item) | Call::PushBack(item) => Some(item),
+            Call::PopFront | Call::PopBack => None,
+        })) {
+            if !pushed.insert(item) {
+                return None;
+            }
+        }
+        for entry in entries {
+            let (Some(item), Call::PopFront | Call::PopBack) = (entry.ret, entry.call) else {
+                continue;
+            };
+            let exit = exits.entry(item).or_insert(Exit { earliest: u64::MAX, ..Exit::default() });
+            exit.earliest = exit.earliest.min(entry.invoked);
+            let end = if entry.call == Call::PopFront { &mut exit.front } else { &mut exit.back };
+            *end = Some(end.map_or(entry.returned, |returned| returned.max(entry.returned)));
+        }
+        Some(Exits(exits))
+    }
+
+    /// Whether every item in `state` could still leave by one end or the other. An item no
+    /// pop got stays for good, so nothing on either side of it can leave past it.
+    fn reachable(&self, state: &VecDeque<u32>) -> bool {
+        let earliest = |item: &u32| self.0.get(item).map_or(u64::MAX, |exit| exit.earliest);
+        let mut leaves = vec![false; state.len()];
+        let mut ahead = 0;
+        for (at, item) in state.iter().enumerate() {
+            leaves[at] = self.0.get(item).and_then(|exit| exit.front).is_some_and(|returned| ahead < returned);
+            ahead = ahead.max(earliest(item));
+        }
+        let mut behind = 0;
+        for (at, item) in state.iter().enumerate().rev() {
+            let exit = self.0.get(item);
+            leaves[at] |= exit.and_then(|exit| exit.back).is_some_and(|returned| behind < returned);
+            if exit.is_none() {
+                leaves[at] = true;
+            }
+            behind = behind.max(earliest(item));
+        }
+        leaves.into_iter().all(|leaves| leaves)
+    }
+}
+
+/// A history no sequential deque could have produced, printed in invocation order.
+pub(crate) struct Unlinearizable(Vec<Entry>);
+
+impl fmt::Debug for Unlinearizable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "no linearization for {} operations:", self.0.len())?;
+        for entry in &self.0 {
+            writeln!(f, "  [{:>6}, {:>6}] {:?} -> {:?}", entry.invoked, entry.returned, entry.call, entry.ret)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `history`, in any order, against a deque that starts out empty.
+#[cfg(test)]
+pub(crate) fn check(history: &[Entry]) -> Result<(), Unlinearizable> {
+    check_from(VecDeque::new(), history)
+}
+
+/// Checks `history` against a deque that starts out holding `initial`.
+#[cfg(test)]
+pub(crate) fn check_from(initial: VecDeque<u32>, history: &[Entry]) -> Result<(), Unlinearizable> {
+    check_bounded(usize::MAX, initial, history)
+}
+
+/// Checks `history` against a deque that starts out holding `initial` and turns pushes away
+/// once it holds `capacity` items.
+pub(crate) fn check_bounded(
+    capacity: usize,
+    initial: VecDeque<u32>,
+    history: &[Entry],
+) -> Result<(), Unlinearizable> {
+    let mut entries = history.to_vec();
+    entries.sort_by_key(|entry| entry.invoked);
+
+    // every invocation and response as one list in time order; `2 * i` is entry `i`'s
+    // invocation and `2 * i + 1` its response
+    let mut events: Vec<usize> = (0..entries.len() * 2).collect();
+    events.sort_by_key(|&event| {
+        let entry = &entries[event / 2];
+        if event.is_multiple_of(2) { entry.invoked } else { entry.returned }
+    });
+    let head = events.len();
+    let mut next = vec![head; events.len() + 1];
+    let mut prev = vec![head; events.len() + 1];
+    let mut at = head;
+    for &event in &events {
+        next[at] = event;
+        prev[event] = at;
+        at = event;
+    }
+
+    // unlinks entry `i`'s invocation and response, or links them back in
+    let lift = |next: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        for event in [2 * i, 2 * i + 1] {
+            next[prev[event]] = next[event];
+            if next[event] != head {
+                prev[next[event]] = prev[event];
+            }
+        }
+    };
+    let unlift = |next: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        for event in [2 * i + 1, 2 * i] {
+            next[prev[event]] = event;
+            if next[event] != head {
+                prev[next[event]] = event;
+            }
+        }
+    };
+
+    // how many other invocations and responses each call spans
+    let mut span = vec![0; entries.len()];
+    for (at, &event) in events.iter().enumerate() {
+        let i = event / 2;
+        span[i] = if event.is_multiple_of(2) { at } else { at - span[i] - 1 };
+    }
+    let exits = Exits::new(&initial, &entries, &span);
+    let mut linearized = vec![0u64; entries.len().div_ceil(64)];
+    let mut seen = HashSet::new();
+    let mut undo: Vec<(usize, VecDeque<u32>)> = vec![];
+    let mut state = initial;
+    let mut event = next[head];
+    while next[head] != head {
+        if event != head && event.is_multiple_of(2) {
+            let i = event / 2;
+            // taking an item out never strands the rest, so only a push needs looking at
+            let after = step(&state, capacity, &entries[i]).filter(|after| {
+                after.len() <= state.len() || exits.as_ref().is_none_or(|exits| exits.reachable(after))
+            });
+            if let Some(after) = after {
+                linearized[i / 64] |= 1 << (i % 64);
+                if seen.insert((linearized.clone(), after.clone())) {
+                    undo.push((i, core::mem::replace(&mut state, after)));
+                    lift(&mut next, &mut prev, i);
+                    event = next[head];
+                    continue;
+                }
+                linearized[i / 64] &= !(1 << (i % 64));
+            }
+            event = next[event];
+        } else {
+            // a response whose call hasn't been linearized yet: undo the latest choice
+            let Some((i, before)) = undo.pop() else {
+                return Err(Unlinearizable(entries));
+            };
+            state = before;
+            linearized[i / 64] &= !(1 << (i % 64));
+            unlift(&mut next, &mut prev, i);
+            event = next[2 * i];
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(call: Call, ret: Option<u32>, invoked: u64, returned: u64) -> Entry {
+        Entry { call, ret, invoked, returned }
+    }
+
+    #[test]
+    fn overlapping_calls_reorder() {
+        // the pop overlaps both pushes, so it may take either end's item
+        let history = [
+            entry(Call::PushFront(1), None, 0, 3),
+            entry(Call::PushBack(2), None, 1, 4),
+            entry(Call::PopFront, Some(2), 2, 5),
+        ];
+        assert!(check(&history).is_ok());
+
+        // a pop that saw nothing, overlapping the push it would otherwise have seen
+        let history = [entry(Call::PopBack, None, 0, 2), entry(Call::PushBack(7), None, 1, 3)];
+        assert!(check(&history).is_ok());
+    }
+
+    #[test]
+    fn real_time_order_is_kept() {
+        // both pushes finished before the pop began, so the front is 1
+        let history = [
+            entry(Call::PushBack(2), None, 0, 1),
+            entry(Call::PushFront(1), None, 2, 3),
+            entry(Call::PopFront, Some(2), 4, 5),
+        ];
+        assert!(check(&history).is_err());
+    }
+
+    #[test]
+    fn lost_and_duplicated_items_are_caught() {
+        let lost = [entry(Call::PushBack(1), None, 0, 1), entry(Call::PopFront, None, 2, 3)];
+        assert!(check(&lost).is_err());
+
+        let duplicated = [
+            entry(Call::PushBack(1), None, 0, 1),
+            entry(Call::PopFront, Some(1), 2, 5),
+            entry(Call::PopBack, Some(1), 3, 4),
+        ];
+        assert!(check(&duplicated).is_err());
+        assert!(check_from(VecDeque::from([1]), &duplicated).is_ok());
+    }
+
+    #[test]
+    fn pushes_are_only_turned_away_when_full() {
+        // the pop overlaps the rejected push, so the deque may still have been full
+        let history = [
+            entry(Call::PushBack(1), None, 0, 1),
+            entry(Call::PushBack(2), Some(2), 2, 4),
+            entry(Call::PopFront, Some(1), 3, 5),
+        ];
+        assert!(check_bounded(1, VecDeque::new(), &history).is_ok());
+        assert!(check_bounded(2, VecDeque::new(), &history).is_err());
+
+        // but not once the pop had finished
+        let history = [
+            entry(Call::PushBack(1), None, 0, 1),
+            entry(Call::PopFront, Some(1), 2, 3),
+            entry(Call::PushBack(2), Some(2), 4, 5),
+        ];
+        assert!(check_bounded(1, VecDeque::new(), &history).is_err());
+    }
+
+    #[test]
+    fn stalled_pushes_are_placed_cheaply() {
+        // three pushes stall across a run that stacks a hundred items onto the front; each
+        // could go under any number of them until the final drain says where
+        let stalled = [(30, 1000), (60, 1001), (90, 1002)];
+        let mut deque = VecDeque::new();
+        let mut history: Vec<_> = stalled.iter().map(|&(_, item)| entry(Call::PushFront(item), None, 0, 1000)).collect();
+        let mut now = 1;
+        for k in 0..100 {
+            if let Some(&(_, item)) = stalled.iter().find(|&&(at, _)| at == k) {
+                deque.push_front(item);
+            }
+            deque.push_front(k);
+            history.push(entry(Call::PushFront(k), None, now, now + 1));
+            history.push(entry(Call::PushFront(500 + k), None, now + 2, now + 3));
+            history.push(entry(Call::PopFront, Some(500 + k), now + 4, now + 5));
+            now += 6;
+        }
+        now = 1001;
+        for item in &deque {
+            history.push(entry(Call::PopFront, Some(*item), now, now + 1));
+            now += 2;
+        }
+        assert!(check(&history).is_ok());
+
+        // but one of the others can't have gone under an item pushed before it
+        let popped = |history: &[Entry], item| history.iter().position(|entry| entry.ret == Some(item)).unwrap();
+        let (a, b) = (popped(&history, 1000), popped(&history, 50));
+        (history[a].ret, history[b].ret) = (Some(50), Some(1000));
+        assert!(check(&history).is_err());
+    }
+
+    #[test]
+    fn recorded_joque_history_checks_out() {
+        let clock = Clock::default();
+        let deque = Joque::new(64);
+        let history: Vec<_> = std::thread::scope(|s| {
+            let ths: Vec<_> = (0..4u32)
+                .map(|t| {
+                    let (deque, clock) = (&deque, &clock);
+                    s.spawn(move || {
+                        let mut rec = Recorder::new(clock);
+                        for i in 0..8 {
+                            rec.push_back(deque, t * 8 + i);
+                            rec.pop_front(deque);
+                            rec.push_front(deque, t * 8 + i);
+                        }
+                        rec.log
+                    })
+                })
+                .collect();
+            ths.into_iter().flat_map(|th| th.join().unwrap()).collect()
+        });
+        assert_eq!(96, history.len());
+        check(&history).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+//! A deque for values small enough to ride in the ring slot itself.
+//!
+//! [`Joque`](crate::Joque) parks each item in a boxed backing record and keeps only the
+//! record's index in the slot word. When the item fits in the 32 bits that index would
+//! occupy, [`InlineJoque`] stores it there directly, so pushes and pops never allocate
+//! and never touch a backing table.
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use crate::backoff::{Attempts, Yield};
+use crate::pad::{CachePadded, Slot, empty_slot};
+use crate::sync::*;
+use crate::{LEFTMASK, Op, Reserved, Ring, demux, mux, next_gen, occupancy, reserve};
+
+/// A value that round-trips through the low half of a ring slot word: 32 bits where the
+/// target has 64-bit atomics, 16 bits under the narrow layout.
+pub trait Inline: Copy {
+    fn into_bits(self) -> u32;
+    fn from_bits(bits: u32) -> Self;
+}
+
+// signed values go through their unsigned twin so they don't sign-extend past their width
+macro_rules! inline_as {
+    ($($t:ty => $u:ty),*) => {$(
+        impl Inline for $t {
+            fn into_bits(self) -> u32 {
+                self as $u as u32
+            }
+
+            fn from_bits(bits: u32) -> Self {
+                bits as $u as $t
+            }
+        }
+    )*};
+}
+
+inline_as!(u8 => u8, u16 => u16, i8 => u8, i16 => u16);
+
+#[cfg(all(target_has_atomic = "64", not(joque_narrow)))]
+inline_as!(u32 => u32, i32 => u32);
+
+impl Inline for bool {
+    fn into_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        bits != 0
+    }
+}
+
+#[cfg(all(target_has_atomic = "64", not(joque_narrow)))]
+impl Inline for char {
+    fn into_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        char::from_u32(bits).expect("slot held a char that wasn't")
+    }
+}
+
+#[cfg(all(target_has_atomic = "64", not(joque_narrow)))]
+impl Inline for f32 {
+    fn into_bits(self) -> u32 {
+        self.to_bits()
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        f32::from_bits(bits)
+    }
+}
+
+// just under the mark at the top of the slot word; the slot's generation keeps the rest of
+// the upper half
+const FULL: Word = 1 << (Word::BITS - 2);
+
+/// InlineJoque is a [`Joque`](crate::Joque) whose items live in the ring slots.
+///
+/// Same extents, same reserve-then-fill protocol, same capacity rules; a slot word is
+/// `FULL | generation << HALF | value` while occupied and just the generation otherwise.
+#[allow(dead_code)]
+pub struct InlineJoque<T: Inline> {
+    deque: Vec<Slot>,
+    leftright: CachePadded<AtomicWord>,
+    capacity: u32,
+    _items: PhantomData<T>,
+}
+
+#[allow(dead_code)]
+impl<T: Inline> InlineJoque<T> {
+    pub fn new(width: u32) -> Self {
+        if width < 5 {
+            panic!("let's not");
+        }
+        if width as u128 > LEFTMASK as u128 {
+            panic!("too wide for {HALF}-bit slot halves");
+        }
+        let left = width / 2;
+        InlineJoque {
+            deque: core::iter::from_fn(|| Some(empty_slot()))
+                .take(width as usize)
+                .collect(),
+            leftright: CachePadded::new(AtomicWord::new(mux(left, left + 1))),
+            capacity: width,
+            _items: PhantomData,
+        }
+    }
+
+    /// Pushes onto the front, waiting for a pop to make room if the ring is full.
+    pub fn push_front(&self, item: T) {
+        let mut attempts = Attempts::new(&Yield);
+        while self.try_push_front(item).is_err() {
+            attempts.snooze();
+        }
+    }
+
+    /// Pushes onto the front, handing `item` back if all slots are taken.
+    pub fn try_push_front(&self, item: T) -> Result<(), T> {
+        let bits = item.into_bits() as Word;
+        assert!(bits <= LEFTMASK, "doesn't fit in {HALF} bits");
+        let reserved = reserve(self, Op::PushFront, &mut Attempts::new(&Yield)).ok_or(item)?;
+        self.publish(reserved.slot, reserved.val, FULL | next_gen(reserved.val) << HALF | bits);
+        Ok(())
+    }
+
+    pub fn pop_front(&self) -> Option<T> {
+        let reserved = reserve(self, Op::PopFront, &mut Attempts::new(&Yield))?;
+        Some(self.drain(reserved))
+    }
+
+    /// Pushes onto the back, waiting for a pop to make room if the ring is full.
+    pub fn push_back(&self, item: T) {
+        let mut attempts = Attempts::new(&Yield);
+        while self.try_push_back(item).is_err() {
+            attempts.snooze();
+        }
+    }
+
+    /// Pushes onto the back, handing `item` back if all slots are taken.
+    pub fn try_push_back(&self, item: T) -> Result<(), T> {
+        let bits = item.into_bits() as Word;
+        assert!(bits <= LEFTMASK, "doesn't fit in {HALF} bits");
+        let reserved = reserve(self, Op::PushBack, &mut Attempts::new(&Yield)).ok_or(item)?;
+        self.publish(reserved.slot, reserved.val, FULL | next_gen(reserved.val) << HALF | bits);
+        Ok(())
+    }
+
+    pub fn pop_back(&self) -> Option<T> {
+        let reserved = reserve(self, Op::PopBack, &mut Attempts::new(&Yield))?;
+        Some(self.drain(reserved))
+    }
+
+    /// Reads the value out of the slot reserved for a pop and clears it.
+    fn drain(&self, Reserved { slot, val }: Reserved) -> T {
+        self.publish(slot, val, next_gen(val) << HALF);
+        T::from_bits((val & LEFTMASK) as u32)
+    }
+
+    /// See [`Joque::len`](crate::Joque::len).
+    pub fn len(&self) -> usize {
+        let (left, right) = demux(self.leftright.load(Ordering::Acquire));
+        occupancy(left, right, self.capacity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize - 1
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len()
+    }
+}
+
+impl<T: Inline> Ring for InlineJoque<T> {
+    fn extents(&self) -> &AtomicWord {
+        &self.leftright
+    }
+
+    fn slot(&self, slot: u32) -> &AtomicWord {
+        &self.deque[slot as usize]
+    }
+
+    fn width(&self) -> u32 {
+        self.capacity
+    }
+
+    fn occupied(val: Word) -> bool {
+        val & FULL != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::InlineJoque;
+
+    #[cfg(not(loom))]
+    #[test]
+    fn inline_basic() {
+        let deque = InlineJoque::new(25);
+
+        deque.push_front(7u16);
+        deque.push_front(0);
+        deque.push_back(u16::MAX);
+
+        assert_eq!(3, deque.len());
+        assert_eq!(Some(0), deque.pop_front());
+        assert_eq!(Some(u16::MAX), deque.pop_back());
+        assert_eq!(Some(7), deque.pop_back());
+        assert_eq!(None, deque.pop_front());
+        assert!(deque.is_empty());
+    }
+
+    #[cfg(all(not(loom), target_has_atomic = "64", not(joque_narrow)))]
+    #[test]
+    fn inline_wide_types() {
+        let wide = InlineJoque::new(8);
+        wide.push_front(u32::MAX);
+        wide.push_front(0xdead_beef);
+        assert_eq!(Some(u32::MAX), wide.pop_back());
+
+        let chars = InlineJoque::new(8);
+        chars.push_back('🦀');
+        chars.push_back('j');
+        assert_eq!(Some('🦀'), chars.pop_front());
+
+        let floats = InlineJoque::new(8);
+        floats.push_front(-0.5f32);
+        assert_eq!(Some(-0.5), floats.pop_back());
+
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn inline_narrow_types() {
+        let signed = InlineJoque::new(8);
+        signed.push_front(-1i16);
+        signed.push_back(i16::MIN);
+        assert_eq!(Some(-1), signed.pop_front());
+        assert_eq!(Some(i16::MIN), signed.pop_front());
+
+        let flags = InlineJoque::new(8);
+        flags.push_back(true);
+        flags.push_back(false);
+        assert_eq!(Some(false), flags.pop_back());
+        assert_eq!(Some(true), flags.pop_back());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn inline_wrap_and_full() {
+        let deque = InlineJoque::new(6);
+        for i in 0..100u16 {
+            deque.push_front(i);
+            assert_eq!(Some(i), deque.pop_back());
+        }
+
+        for i in 0..deque.capacity() as u16 {
+            deque.push_back(i);
+        }
+        assert_eq!(Err(9), deque.try_push_front(9));
+        assert_eq!(Some(0), deque.pop_front());
+        assert!(deque.try_push_front(9).is_ok());
+    }
+
+    #[cfg(all(not(loom), not(miri)))]
+    #[test]
+    fn inline_interleaved() {
+        use std::sync::Arc;
+
+        let THREAD_COUNT = 16u16;
+        let deque = Arc::new(InlineJoque::new(1024));
+        let ths: Vec<_> = (0..THREAD_COUNT)
+            .map(|idx| {
+                let big_deque = deque.clone();
+                std::thread::spawn(move || {
+                    let mut popped = vec![];
+                    for i in 0..32 {
+                        big_deque.push_back(idx * 1000 + i);
+                        big_deque.push_front(idx * 1000 + i);
+                        popped.push(big_deque.pop_back().unwrap());
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        // every value went in twice; between the pops and what's left, it comes out twice
+        let mut seen: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        seen.extend(std::iter::from_fn(|| deque.pop_front()));
+        seen.sort();
+        let mut expected: Vec<_> = (0..THREAD_COUNT)
+            .flat_map(|idx| (0..32).flat_map(move |i| [idx * 1000 + i; 2]))
+            .collect();
+        expected.sort();
+        assert_eq!(expected, seen);
+    }
+}
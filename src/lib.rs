@@ -1,311 +1,907 @@
+#![cfg_attr(not(any(feature = "std", loom, test)), no_std)]
 #![allow(non_snake_case)]
-#![allow(unreachable_code)]
+// `Word` is u64 or u32 depending on the target, so casts that are no-ops on one are needed on the other
+#![allow(clippy::unnecessary_cast)]
 // use std::thread;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::{alloc::Layout, marker::PhantomData, ptr, ptr::NonNull};
+
+use crate::backoff::Attempts;
+#[cfg(feature = "alloc")]
+use crate::backoff::RetryCounts;
+#[cfg(feature = "alloc")]
+use crate::combining::Combining;
+#[cfg(feature = "alloc")]
+use crate::dump::Dumped;
+#[cfg(feature = "alloc")]
+use crate::elimination::Elimination;
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, Shard};
 use crate::sync::*;
+#[cfg(feature = "alloc")]
+use crate::pad::{CachePadded, Slot, empty_slot};
+
+mod backoff;
+mod fixed;
+mod pad;
+pub use backoff::{Backoff, Exponential, Retries, Spin, Yield};
+#[cfg(feature = "std")]
+pub use backoff::ParkAfter;
+pub use fixed::StaticJoque;
+
+#[cfg(feature = "alloc")]
+mod combining;
+#[cfg(feature = "alloc")]
+mod dump;
+#[cfg(feature = "alloc")]
+mod elimination;
+#[cfg(all(feature = "fault-injection", not(loom)))]
+pub mod faults;
+#[cfg(all(any(test, feature = "fuzz"), feature = "alloc", not(loom)))]
+pub mod fuzz;
+#[cfg(all(any(test, feature = "fuzz"), feature = "alloc", not(loom)))]
+mod history;
+#[cfg(feature = "alloc")]
+mod inline;
+#[cfg(feature = "alloc")]
+pub use inline::{Inline, InlineJoque};
+#[cfg(all(test, feature = "alloc", not(loom)))]
+mod model;
+
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "metrics")]
+pub use metrics::JoqueStats;
+
+#[cfg(feature = "alloc")]
+mod records;
+#[cfg(feature = "alloc")]
+pub use records::{Global, RecordAllocator};
+
+#[cfg(all(feature = "trace", not(loom)))]
+pub mod trace;
+#[cfg(feature = "alloc")]
+mod validate;
+#[cfg(feature = "alloc")]
+pub use validate::{Report, Violation};
+#[cfg(all(feature = "wide", target_arch = "x86_64", not(loom)))]
+mod wide;
+
+#[cfg(all(feature = "wide", joque_narrow))]
+compile_error!("`wide` and `--cfg joque_narrow` pull the slot layout in opposite directions");
 
 mod sync {
+    // the pointers and bitmap words are only for the heap-backed `Joque`
     #[cfg(loom)]
-    pub(crate) use loom::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+    #[allow(unused_imports)]
+    pub(crate) use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+
+    // `fault-injection` swaps in atomics that check for a due fault at every step
+    #[cfg(all(not(loom), not(feature = "fault-injection")))]
+    use core::sync::atomic;
+
+    #[cfg(all(not(loom), feature = "fault-injection"))]
+    use crate::faults::atomic;
+
+    #[cfg(not(loom))]
+    #[allow(unused_imports)]
+    pub(crate) use {
+        atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize},
+        core::sync::atomic::Ordering,
+    };
+
+    // Every packed word (the extents, each ring slot) is two halves of one atomic. The `wide`
+    // feature makes that 128 bits on x86_64; without it, targets that can CAS 64 bits get
+    // 32-bit halves, and the rest, or any build with `--cfg joque_narrow`, make do with
+    // 16-bit halves of a 32-bit word. Op tags are as wide as a half can hold; indices never
+    // need more than 32 bits, whatever the layout (see `MAX_WIDTH`).
+    #[cfg(all(feature = "wide", target_arch = "x86_64", not(loom)))]
+    pub(crate) use crate::wide::{AtomicWide as AtomicWord, Tag, Word};
+
+    #[cfg(not(all(feature = "wide", target_arch = "x86_64", not(loom))))]
+    pub(crate) use packed::*;
+
+    #[cfg(not(all(feature = "wide", target_arch = "x86_64", not(loom))))]
+    mod packed {
+        #[cfg(all(loom, target_has_atomic = "64", not(joque_narrow)))]
+        pub(crate) use loom::sync::atomic::AtomicU64 as AtomicWord;
+
+        #[cfg(all(loom, any(not(target_has_atomic = "64"), joque_narrow)))]
+        pub(crate) use loom::sync::atomic::AtomicU32 as AtomicWord;
+
+        #[cfg(all(not(loom), target_has_atomic = "64", not(joque_narrow)))]
+        pub(crate) use super::atomic::AtomicU64 as AtomicWord;
+
+        #[cfg(all(not(loom), any(not(target_has_atomic = "64"), joque_narrow)))]
+        pub(crate) use super::atomic::AtomicU32 as AtomicWord;
+
+        #[cfg(all(target_has_atomic = "64", not(joque_narrow)))]
+        pub(crate) type Word = u64;
+
+        #[cfg(any(not(target_has_atomic = "64"), joque_narrow))]
+        pub(crate) type Word = u32;
+
+        pub(crate) type Tag = u32;
+    }
+
+    /// Bits in each half of a packed word.
+    pub(crate) const HALF: u32 = Word::BITS / 2;
 
+    /// Called while waiting on a slot another thread has reserved but not yet filled or cleared.
+    #[cfg(loom)]
+    pub(crate) fn relax() {
+        loom::thread::yield_now();
+    }
+
+    /// Called while waiting on a slot another thread has reserved but not yet filled or cleared.
+    #[cfg(all(not(loom), feature = "std"))]
+    pub(crate) fn relax() {
+        std::thread::yield_now();
+    }
+
+    /// Without an OS to yield to, the best a waiter can do is spin.
+    #[cfg(all(not(loom), not(feature = "std")))]
+    pub(crate) fn relax() {
+        spin(1);
+    }
+
+    /// Busy-waits for `rounds` pause instructions. Loom can't see a spin, so it yields once.
+    #[cfg(loom)]
+    pub(crate) fn spin(_rounds: u32) {
+        loom::thread::yield_now();
+    }
+
+    /// Busy-waits for `rounds` pause instructions.
     #[cfg(not(loom))]
-    pub(crate) use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+    pub(crate) fn spin(rounds: u32) {
+        for _ in 0..rounds {
+            core::hint::spin_loop();
+        }
+    }
 }
 
-/// Joque implements a lock-free double-ended queue.
+/// Joque implements a concurrent double-ended queue.
+///
+/// The live items sit strictly between the left and right extents packed in `leftright`. An
+/// operation first reserves its ring slot by moving its extent, then fills or clears the slot,
+/// so the extents always count every operation that has been admitted, finished or not.
+///
+/// Every operation takes effect at the CAS that moves its extent, so the deque is
+/// linearizable. It isn't lock-free: an operation holds its slot from just before that CAS
+/// until the slot is filled or cleared, and the next operation to want the same slot waits
+/// for it, however long the thread holding it is descheduled.
+///
+/// # Panic safety
+///
+/// Pushes and pops never run `T`'s destructor, so a panicking `Drop` can only surface in the
+/// caller's hands or while the deque itself is dropped. In the latter case the rest of the
+/// queued items and every record are still freed as the panic unwinds. A second destructor
+/// panicking during that unwind aborts the process, same as it would for a `Vec`.
+///
+/// Backing records come from `A`, the global allocator unless the deque was built with
+/// [`Joque::new_in`]. Contended retries wait as `B` says, [`Yield`] unless the deque was
+/// built with [`Joque::with_backoff`].
+#[cfg(feature = "alloc")]
 #[allow(dead_code)]
-struct Joque<T> {
-    // Contains an op_id muxed with a "pointer" into backing
-    deque: Vec<AtomicUsize>,
-    leftright: AtomicUsize,
+pub struct Joque<T: ?Sized, A: RecordAllocator = Global, B: Backoff = Yield> {
+    // Contains a slot generation muxed with a "pointer" into backing
+    deque: Vec<Slot>,
+    leftright: CachePadded<AtomicWord>,
     capacity: u32, // size of heap
 
-    // 💡✨: 128 entry chunks, with 128/bitwise find empty, NO GC.
     // 💡✨: multiple reclamation stacks, thread across them when doing reclamation
     // caller responsible for predicting max simult. writers
     backing: Vec<RecordJoque<T>>, // zero is the null ptr in this reference frame
-    op_id: AtomicU32,
-    idx: AtomicU32,
+    claimed: Vec<AtomicUsize>, // one bit per backing record, set while a push or a live slot owns it
+    idx: CachePadded<AtomicU32>, // where the next claim starts scanning `claimed`
+    retries: CachePadded<RetryCounts>,
+    elimination: Elimination<T>, // empty unless built `eliminating`
+    combining: Combining<T>,     // empty unless built `combining`
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    alloc: A,
+    backoff: B,
+    _owns: PhantomData<T>,
+}
+
+#[cfg(feature = "alloc")]
+struct RecordJoque<T: ?Sized>(AtomicPtr<Record<T>>);
+
+/// An op tag and the item pushed under it. The item is boxed on its own, so unsized
+/// `T`s like `dyn FnOnce()` fit without another layer of boxing.
+#[cfg(feature = "alloc")]
+type Record<T> = (Tag, Option<Box<T>>);
+
+// Items only ever move through the deque, never get shared by it.
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized + Send, A: RecordAllocator + Send, B: Backoff + Send> Send for Joque<T, A, B> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized + Send, A: RecordAllocator + Sync, B: Backoff + Sync> Sync for Joque<T, A, B> {}
+
+const LEFTMASK: Word = (1 << HALF) - 1;
+const RIGHTMASK: Word = LEFTMASK << HALF;
+
+/// Set in a ring slot word while an operation holds the slot; see [`reserve_with`].
+const MARK: Word = 1 << (Word::BITS - 1);
+
+/// A slot's generation, under the two flag bits at the top of the word: [`MARK`], and the
+/// full bit of an `InlineJoque` slot.
+const GENMASK: Word = LEFTMASK >> 2;
+
+/// The widest [`Joque`]: four backing records per slot, and every record index has to fit in
+/// both a slot half and the `u32`s they're handed round in.
+#[cfg(feature = "alloc")]
+const MAX_WIDTH: u32 = if HALF < 32 { 1 << (HALF - 2) } else { 1 << 30 };
+
+#[cfg(feature = "alloc")]
+const BITS: usize = usize::BITS as usize;
+
+/// The four things you can do to a deque; each one moves exactly one extent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    PushFront,
+    PopFront,
+    PushBack,
+    PopBack,
+}
+
+impl Op {
+    #[allow(dead_code)]
+    pub(crate) fn is_push(self) -> bool {
+        matches!(self, Op::PushFront | Op::PushBack)
+    }
 }
 
-struct RecordJoque<T>(AtomicPtr<(u32, *mut T)>);
+/// The parts of a deque [`reserve`] works on, shared by every deque in the crate.
+trait Ring {
+    fn extents(&self) -> &AtomicWord;
 
-const LEFTMASK: usize = 0x00000000_FFFFFFFF;
-const RIGHTMASK: usize = 0xFFFFFFFF_00000000;
-const ONE: usize = 0x00000001_00000000;
+    fn slot(&self, slot: u32) -> &AtomicWord;
+
+    /// Ring slots, one more than the deque holds.
+    fn width(&self) -> u32;
+
+    /// Whether an unmarked slot word holds an item.
+    fn occupied(val: Word) -> bool {
+        val & LEFTMASK != 0
+    }
+
+    /// Swaps the mark on `slot`, which held `val` when it was marked, for `next`. Nothing
+    /// but the mark's owner writes a marked slot, so this can't lose.
+    fn publish(&self, slot: u32, val: Word, next: Word) {
+        let cas = self.slot(slot).compare_exchange(val | MARK, next, Ordering::Release, Ordering::Relaxed);
+        #[cfg(all(feature = "trace", not(loom)))]
+        trace::slot(self.extents(), slot, val | MARK, next, cas.is_ok());
+        debug_assert!(cas.is_ok(), "slot {slot} changed while it was marked");
+    }
+}
+
+/// A slot [`reserve`] handed out, and what it held before it was marked. The operation
+/// finishes by [publishing](Ring::publish) the slot's next word over the mark.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Reserved {
+    slot: u32,
+    val: Word,
+}
+
+/// What an attempt at [`reserve_with`] lost to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Lost {
+    /// Another operation moved the extents first.
+    Extents,
+    /// Another operation holds the slot, or marked it first.
+    Slot,
+}
+
+/// Reserves the ring slot `op` works on and moves the extent past it, returning the slot,
+/// or `None` when the ring is full (pushes) or empty (pops). Every attempt lost to another
+/// operation costs `attempts` a snooze.
+fn reserve<Q: Ring + ?Sized, B: Backoff + ?Sized>(ring: &Q, op: Op, attempts: &mut Attempts<'_, B>) -> Option<Reserved> {
+    let lost = |_, attempts: &mut Attempts<'_, B>| {
+        attempts.snooze();
+        None::<core::convert::Infallible>
+    };
+    let Ok(reserved) = reserve_with(ring, op, attempts, lost);
+    reserved
+}
+
+/// [`reserve`], handing each lost attempt to `lost` instead of just snoozing. `lost` either
+/// finishes the operation some other way, and `reserve_with` returns `Err` with what it
+/// came back with, or snoozes `attempts` itself and lets the attempt go round again.
+///
+/// An operation marks its slot before it CASes the extents and keeps the mark until it has
+/// filled or drained the slot, and a slot is only marked when its state is the one the
+/// operation expects: empty for a push, full for a pop. Nothing else writes a marked slot,
+/// so an unmarked slot is full exactly when it sits between the extents, and a pop takes
+/// the very item its slot held when its CAS on the extents landed.
+fn reserve_with<Q: Ring + ?Sized, B: Backoff + ?Sized, R>(
+    ring: &Q,
+    op: Op,
+    attempts: &mut Attempts<'_, B>,
+    mut lost: impl FnMut(Lost, &mut Attempts<'_, B>) -> Option<R>,
+) -> Result<Option<Reserved>, R> {
+    let (leftright, capacity) = (ring.extents(), ring.width());
+    loop {
+        let muxed = leftright.load(Ordering::Acquire);
+        let (left, right) = demux(muxed);
+        let (claimed, left, right) = match op {
+            // the slot at `left`, moving `left` outward
+            Op::PushFront if left != right => (left, wrap_dec(left, capacity), right),
+            // the slot just inside `left`, moving `left` inward
+            Op::PopFront if wrap_inc(left, capacity) != right => {
+                let claimed = wrap_inc(left, capacity);
+                (claimed, claimed, right)
+            }
+            Op::PushBack if left != right => (right, left, wrap_inc(right, capacity)),
+            Op::PopBack if wrap_dec(right, capacity) != left => {
+                let claimed = wrap_dec(right, capacity);
+                (claimed, left, claimed)
+            }
+            _ => {
+                #[cfg(all(feature = "trace", not(loom)))]
+                trace::refused(leftright, muxed);
+                return Ok(None);
+            }
+        };
+        let word = ring.slot(claimed);
+        let val = word.load(Ordering::Acquire);
+        let lost_to = if val & MARK != 0 {
+            // the operation before us at this slot hasn't finished with it
+            Lost::Slot
+        } else if Q::occupied(val) == op.is_push() {
+            // filled or drained since we read the extents, so they've moved on
+            Lost::Extents
+        } else {
+            let cas = word.compare_exchange_weak(val, val | MARK, Ordering::Acquire, Ordering::Relaxed);
+            #[cfg(all(feature = "trace", not(loom)))]
+            trace::slot(leftright, claimed, val, val | MARK, cas.is_ok());
+            if cas.is_err() {
+                Lost::Slot
+            } else {
+                let cas = leftright.compare_exchange_weak(muxed, mux(left, right), Ordering::AcqRel, Ordering::Acquire);
+                #[cfg(all(feature = "trace", not(loom)))]
+                trace::extents(leftright, claimed, muxed, mux(left, right), cas.is_ok());
+                if cas.is_ok() {
+                    return Ok(Some(Reserved { slot: claimed, val }));
+                }
+                // let the slot go; where the extents are now, it may not be ours to take
+                ring.publish(claimed, val, val);
+                Lost::Extents
+            }
+        };
+        if let Some(done) = lost(lost_to, attempts) {
+            return Err(done);
+        }
+    }
+}
+
+/// Items strictly between the extents.
+fn occupancy(left: u32, right: u32, capacity: u32) -> usize {
+    (right as usize + capacity as usize - left as usize - 1) % capacity as usize
+}
+
+fn wrap_inc(pos: u32, capacity: u32) -> u32 {
+    if pos + 1 == capacity { 0 } else { pos + 1 }
+}
+
+fn wrap_dec(pos: u32, capacity: u32) -> u32 {
+    if pos == 0 { capacity - 1 } else { pos - 1 }
+}
+
+const fn mux(left: u32, right: u32) -> Word {
+    left as Word | ((right as Word) << HALF)
+}
+
+/// The tag for the next write to a slot currently holding `val`: one past the slot's own.
+/// Each slot counts its own generations, so tagging a write touches no shared counter and a
+/// slot word only repeats once that slot's tag wraps.
+fn next_gen(val: Word) -> Word {
+    (generation(val) + 1) & genmask()
+}
+
+/// The generation of the slot word `val`, flag bits left out.
+fn generation(val: Word) -> Word {
+    (val >> HALF) & genmask()
+}
 
-impl<T> Drop for Joque<T> {
+/// [`GENMASK`], unless the `wide` words had to fall back to 64 bits on this CPU.
+fn genmask() -> Word {
+    #[cfg(all(feature = "wide", target_arch = "x86_64", not(loom)))]
+    if !wide::has_cmpxchg16b() {
+        return wide::FALLBACK_GENMASK;
+    }
+    GENMASK
+}
+
+fn demux(muxed: Word) -> (u32, u32) {
+    let left_demuxed = muxed & LEFTMASK;
+    let right_demuxed = (muxed & RIGHTMASK) >> HALF;
+    (left_demuxed as u32, right_demuxed as u32)
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> Drop for Joque<T, A, B> {
         fn drop(&mut self) {
-            unsafe {
-                while let Some(rec) = self.backing.pop() {
-                    let atm = Box::from_raw(rec.0.into_inner());
-                    if !atm.1.is_null() {
-                        let _ = Box::from_raw(atm.1);
-                        // drop inner pointer
-                    }
-                    // drop recordjoque
+            /// Picks the teardown back up if an item's destructor panics part way through.
+            struct Unwinding<'a, T: ?Sized, A: RecordAllocator, B: Backoff>(&'a mut Joque<T, A, B>);
+
+            impl<T: ?Sized, A: RecordAllocator, B: Backoff> Drop for Unwinding<'_, T, A, B> {
+                fn drop(&mut self) {
+                    self.0.teardown();
                 }
-                while let Some(_) = self.deque.pop() { }
             }
+
+            let guard = Unwinding(self);
+            let orphans = guard.0.teardown();
+            // it got all the way through, so there's nothing left to pick up
+            core::mem::forget(guard);
+            debug_assert_eq!(0, orphans, "records orphaned from the ring");
         }
 }
 
-#[allow(dead_code)]
-impl<T> Joque<T> {
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> Ring for Joque<T, A, B> {
+    fn extents(&self) -> &AtomicWord {
+        &self.leftright
+    }
+
+    fn slot(&self, slot: u32) -> &AtomicWord {
+        &self.deque[slot as usize]
+    }
+
+    fn width(&self) -> u32 {
+        self.capacity
+    }
+}
 
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> Joque<T> {
     pub fn new(width: u32) -> Self {
+        Joque::new_in(width, Global)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, B: Backoff> Joque<T, Global, B> {
+    /// Like [`Joque::new`], waiting out contended retries as `backoff` says.
+    pub fn with_backoff(width: u32, backoff: B) -> Self {
+        Joque::new_in_with_backoff(width, Global, backoff)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, A: RecordAllocator> Joque<T, A> {
+    /// Like [`Joque::new`], with every backing record allocated from `alloc`.
+    pub fn new_in(width: u32, alloc: A) -> Self {
+        Joque::new_in_with_backoff(width, alloc, Yield)
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[allow(dead_code)]
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> Joque<T, A, B> {
+    /// The widest ring the constructors take: 2^30 slots, or 2^14 under `--cfg joque_narrow`.
+    pub const MAX_WIDTH: u32 = MAX_WIDTH;
+
+    /// Like [`Joque::new_in`] and [`Joque::with_backoff`] at once.
+    pub fn new_in_with_backoff(width: u32, alloc: A, backoff: B) -> Self {
         if width < 5 {
             panic!("let's not");
         }
+        if width > MAX_WIDTH {
+            panic!("too wide: at most {MAX_WIDTH} slots");
+        }
         let left = width / 2;
-        let right = (left as usize + 1) << 32;
+        let right = left + 1;
+        let records = width as usize * 4;
         Joque {
-            deque: std::iter::from_fn(|| Some(AtomicUsize::new(0)))
+            deque: core::iter::from_fn(|| Some(empty_slot()))
                 .take(width as usize)
                 .collect(), // TODO: 💀 dynamically resizable
-            leftright: AtomicUsize::new(left as usize | right),
+            leftright: CachePadded::new(AtomicWord::new(mux(left, right))),
             capacity: width,
-            backing: std::iter::from_fn(|| Some(Joque::build_blank_rj()))
-                .take(width as usize * 4)
+            backing: core::iter::from_fn(|| Some(Self::build_blank_rj(&alloc)))
+                .take(records)
                 .collect(), // TODO: 💀 dynamically resizable
-            op_id: AtomicU32::new(0),
-            idx: AtomicU32::new(1),
+            claimed: (0..records.div_ceil(BITS))
+                .map(|word| {
+                    // record 0 is the null ptr, and bits past the end of `backing` never free up
+                    let mut bits = if word == 0 { 1 } else { 0 };
+                    for bit in 0..BITS {
+                        if word * BITS + bit >= records {
+                            bits |= 1 << bit;
+                        }
+                    }
+                    AtomicUsize::new(bits)
+                })
+                .collect(),
+            idx: CachePadded::new(AtomicU32::new(0)),
+            retries: CachePadded::new(RetryCounts::new()),
+            elimination: Elimination::new(0),
+            combining: Combining::new(0),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(),
+            alloc,
+            backoff,
+            _owns: PhantomData,
         }
     }
 
-    fn build_blank_rj() -> RecordJoque<T> {
-        RecordJoque(AtomicPtr::new(Box::into_raw(Box::new((
-            u32::MAX,
-            std::ptr::null_mut(),
-        )))))
+    /// Gives each end an elimination array of `width` cells, where a push and a pop that
+    /// collide on the extents can hand the item over directly instead of retrying. Worth it
+    /// for stack-like use, where both ends of a pair work the same end; see
+    /// [`Joque::retries`] for how much contention there is to eliminate.
+    pub fn eliminating(mut self, width: usize) -> Self {
+        self.elimination = Elimination::new(width);
+        self
     }
 
-    fn build_raw_null_rj() -> *mut (u32, *mut T) {
-        Box::into_raw(Box::new((u32::MAX, std::ptr::null_mut())))
+    /// Lets operations that keep losing the CAS on the extents publish themselves in one of
+    /// `width` request cells and have a single combining thread carry them out, until the
+    /// contention dies down again. Roughly one cell per thread that might be operating at
+    /// once; [`Retries::combined`] counts the operations that went that way.
+    pub fn combining(mut self, width: usize) -> Self {
+        self.combining = Combining::new(width);
+        self
     }
 
-    fn build_raw_rj(op_id: u32, item: Box<T>) -> *mut (u32, *mut T) {
-        Box::into_raw(Box::new((op_id, Box::into_raw(item))))
+    fn build_blank_rj(alloc: &A) -> RecordJoque<T> {
+        RecordJoque(AtomicPtr::new(Self::build_raw_null_rj(alloc)))
     }
 
-    fn release_null_rj(raw_rj: *mut (u32, *mut T)) -> Option<Box<T>> {
-        unsafe { 
-            let n = Box::from_raw(raw_rj);
-            if !n.1.is_null() { 
-                let m = Box::from_raw(n.1);
-                return Some(m);
-            } else {
-                None 
-            }// free null
-        } // free rj
+    fn build_raw_null_rj(alloc: &A) -> *mut Record<T> {
+        Self::alloc_rj(alloc, (Tag::MAX, None))
     }
 
-    pub fn push_front(&self, item: Box<T>) {
-        let backing_idx = self.idx.fetch_add(1, Ordering::Relaxed); // TODO: 💀 after 400 write/read cycles
-        loop {
-            let this_left = self.fetch_extent_rel().0 % self.capacity;
-            // println!("Trying to push {this_left}");
-            let lval = self.deque[(this_left % self.capacity) as usize].load(Ordering::Relaxed);
-            let entry = ((self.op_id.fetch_add(1, Ordering::Relaxed) as usize + 1) << 32)
-                | backing_idx as usize;
-            if self.deque[(this_left % self.capacity) as usize]
-                .compare_exchange(lval, entry, Ordering::Acquire, Ordering::Relaxed)
-                .is_ok()
-            {
-                let success_op = ((entry & RIGHTMASK) >> 32) as u32;
-                let release = self.backing[backing_idx as usize]
-                    .0
-                    .swap(Joque::build_raw_rj(success_op, item), Ordering::AcqRel);
-                Joque::release_null_rj(release);
-                self.leftright.fetch_sub(1, Ordering::Acquire);
-                break;
-            }
+    fn build_raw_rj(alloc: &A, tag: Tag, item: Box<T>) -> *mut Record<T> {
+        Self::alloc_rj(alloc, (tag, Some(item)))
+    }
+
+    fn alloc_rj(alloc: &A, record: Record<T>) -> *mut Record<T> {
+        let raw_rj = alloc.allocate(Layout::new::<Record<T>>()).cast::<Record<T>>();
+        unsafe { raw_rj.write(record) };
+        raw_rj.as_ptr()
+    }
+
+    fn release_null_rj(alloc: &A, raw_rj: *mut Record<T>) -> Option<Box<T>> {
+        unsafe {
+            let record = raw_rj.read();
+            alloc.deallocate(NonNull::new_unchecked(raw_rj).cast(), Layout::new::<Record<T>>()); // free rj
+            record.1
         }
     }
 
+    /// Pushes onto the front, waiting for a pop to make room if the ring is full; see
+    /// [`Joque::try_push_front`] to get the item back instead.
+    pub fn push_front(&self, item: Box<T>) {
+        self.push_waiting(Op::PushFront, item)
+    }
+
+    /// Pushes onto the front, handing `item` back if all [`Joque::capacity`] slots are taken.
+    pub fn try_push_front(&self, item: Box<T>) -> Result<(), Box<T>> {
+        self.push(Op::PushFront, item)
+    }
+
     pub fn pop_front(&self) -> Option<Box<T>> {
+        self.pop(Op::PopFront)
+    }
+
+    /// Pushes onto the back, waiting for a pop to make room if the ring is full; see
+    /// [`Joque::try_push_back`] to get the item back instead.
+    pub fn push_back(&self, item: Box<T>) {
+        self.push_waiting(Op::PushBack, item)
+    }
+
+    /// Pushes onto the back, handing `item` back if all [`Joque::capacity`] slots are taken.
+    pub fn try_push_back(&self, item: Box<T>) -> Result<(), Box<T>> {
+        self.push(Op::PushBack, item)
+    }
+
+    pub fn pop_back(&self) -> Option<Box<T>> {
+        self.pop(Op::PopBack)
+    }
+
+    /// Retries a push the ring refused, backing off in between, until it gets in.
+    fn push_waiting(&self, op: Op, mut item: Box<T>) {
+        let mut attempts = Attempts::new(&self.backoff);
+        while let Err(refused) = self.push(op, item) {
+            item = refused;
+            attempts.snooze();
+        }
+    }
+
+    fn push(&self, op: Op, item: Box<T>) -> Result<(), Box<T>> {
+        let raw_rj = Self::build_raw_rj(&self.alloc, Tag::MAX, item);
+        #[cfg(all(feature = "trace", not(loom)))]
+        trace::begin(&self.leftright, op, self.capacity);
+        let refused = self.settle(op, raw_rj);
+        #[cfg(all(feature = "trace", not(loom)))]
+        trace::end(&self.leftright, refused.is_null());
+        #[cfg(feature = "metrics")]
+        {
+            let shard = self.metrics.shard();
+            Shard::bump(match (refused.is_null(), op) {
+                (true, Op::PushFront) => &shard.pushed_front,
+                (true, _) => &shard.pushed_back,
+                (false, _) => &shard.full,
+            });
+        }
+        if refused.is_null() {
+            return Ok(());
+        }
+        Err(Self::release_null_rj(&self.alloc, refused).expect("record lost its item"))
+    }
+
+    fn pop(&self, op: Op) -> Option<Box<T>> {
         loop {
-            let (sens_left, sens_right) = self.fetch_extent_rel();
-            if sens_left % self.capacity == sens_right % self.capacity {
-                return None;
-            }
-            let this_left = sens_left + 1 % self.capacity;
-            let lval = self.deque[(this_left % self.capacity) as usize].load(Ordering::Acquire);
-            if lval & LEFTMASK == 0 {
+            #[cfg(all(feature = "trace", not(loom)))]
+            trace::begin(&self.leftright, op, self.capacity);
+            let out = self.settle(op, ptr::null_mut());
+            #[cfg(all(feature = "trace", not(loom)))]
+            trace::end(&self.leftright, !out.is_null());
+            if out.is_null() {
+                #[cfg(feature = "metrics")]
+                Shard::bump(&self.metrics.shard().empty);
                 return None;
             }
-            let idx = 0;
-            let entry = ((self.op_id.fetch_add(1, Ordering::Acquire) as usize + 1) << 32) | idx;
-            if let Ok(old_one) = self.deque[(this_left % self.capacity) as usize].compare_exchange(
-                lval,
-                entry,
-                Ordering::Release,
-                Ordering::Relaxed,
-            ) {
-                // println!("Seeking from {}, ok", lval & LEFTMASK);
-                let out = self.backing[(lval & LEFTMASK) as usize]
-                    .0
-                    .swap(Joque::build_raw_null_rj(), Ordering::AcqRel);
-
-                unsafe {
-                    let output = Box::from_raw(out);
-                    if (*output).1.is_null() {
-                        // println!("Well that was weird I couldn't pull {} cause out was null", lval & LEFTMASK);
-                        // println!("Here's some other stuff: ");
-                        // println!("Directed pop state: {this_left}");
-                        // println!("Claimed Op ID: {}", entry >> 32);
-                        // println!("Old Cmp Data: {} {}", old_one & RIGHTMASK >> 32, old_one & LEFTMASK);
-                        // panic!("This should not occur");
-                        // TODO: 💀 is this even recoverable? ... how?
-                        // panic!("should never happen;");
-                        return None;
-                    } else if (*output).0 == ((old_one & RIGHTMASK) >> 32) as u32 {
-                        self.leftright.fetch_add(1, Ordering::Release);
-                        let response = Box::from_raw((*output).1);
-                        return Some(response);
-                    } else {
-                        // println!("Well that was weird I couldn't pull {} cause the thingies no matchy", lval & LEFTMASK);
-                        // println!("the thingies are {} and {}", (*out).0, ((old_one & RIGHTMASK) >> 32) as u32);
-                        // println!("Here's some other stuff: ");
-                        // println!("Directed pop state: {this_left}");
-                        // println!("Claimed Op ID: {}", entry >> 32);
-                        // println!("Old Cmp Data: {} {}", old_one & RIGHTMASK >> 32, old_one & LEFTMASK);
-                        panic!("also should never happen;");
-                        println!("Oh shit, oh shit, oh shit, put it back!!");
-                        self.backing[(lval & LEFTMASK) as usize]
-                            .0
-                            .store(out, Ordering::Release); // TODO: 💀 maybe a memory leak, see `release_null_rj`
-                        return None;
-                    }
+            // `drain` asserts the record held an item; if it didn't, the slot is spent all
+            // the same, so go round again rather than report the deque empty
+            if let Some(item) = Self::release_null_rj(&self.alloc, out) {
+                #[cfg(feature = "metrics")]
+                {
+                    let shard = self.metrics.shard();
+                    Shard::bump(if op == Op::PopFront { &shard.popped_front } else { &shard.popped_back });
                 }
+                return Some(item);
             }
+            #[cfg(feature = "metrics")]
+            Shard::bump(&self.metrics.shard().spurious_none);
         }
     }
 
-    pub fn push_back(&self, item: Box<T>) {
-        // reserve backing storage
-        //  - unique until wrapped
-        let backing_idx = self.idx.fetch_add(1, Ordering::Relaxed); // TODO: 💀 after 400 write/read cycles
-        loop {
-            let this_right = self.fetch_extent_rel().1;
-            let rval = self.deque[(this_right % self.capacity) as usize].load(Ordering::Relaxed);
-            let entry = ((self.op_id.fetch_add(1, Ordering::Relaxed) as usize + 1) << 32)
-                | backing_idx as usize;
-            if self.deque[(this_right % self.capacity) as usize]
-                .compare_exchange(rval, entry, Ordering::Acquire, Ordering::Relaxed)
-                .is_ok()
+    /// Carries out `op` one way or another. A push hands over the record holding its item
+    /// and gets it back only if the ring was full; a pop hands over null and gets back the
+    /// record it took, or null if there was none.
+    ///
+    /// The direct way is to reserve a slot and fill or drain it. Each CAS on the extents lost
+    /// on the way gives the operation a chance to go another: to a combiner, once it has lost
+    /// enough of them or while the deque is hot, or to an operation at the same end waiting
+    /// in the elimination array. Waiting on a slot another operation holds just snoozes.
+    fn settle(&self, op: Op, raw_rj: *mut Record<T>) -> *mut Record<T> {
+        let apply = |op, raw_rj| self.apply(op, raw_rj);
+        let mut attempts = Attempts::new(&self.backoff);
+        if self.combining.is_hot()
+            && let Some(out) = self.combining.submit(op, raw_rj, &mut attempts, apply)
+        {
+            #[cfg(all(feature = "trace", not(loom)))]
+            trace::handoff(&self.leftright);
+            return out;
+        }
+        let mut waits = Attempts::new(&self.backoff);
+        let reserved = reserve_with(self, op, &mut attempts, |lost, attempts| {
+            if lost == Lost::Slot {
+                #[cfg(feature = "metrics")]
+                Shard::bump(if op.is_push() { &self.metrics.shard().fill_lost } else { &self.metrics.shard().drain_lost });
+                waits.snooze();
+                return None;
+            }
+            #[cfg(feature = "metrics")]
+            Shard::bump(&self.metrics.shard().extents_lost);
+            if self.combining.wants(attempts)
+                && let Some(out) = self.combining.submit(op, raw_rj, attempts, apply)
             {
-                let success_op = ((entry & RIGHTMASK) >> 32) as u32;
-                let release = self.backing[backing_idx as usize]
-                    .0
-                    .swap(Joque::build_raw_rj(success_op, item), Ordering::AcqRel);
-                Joque::release_null_rj(release);
-                self.leftright.fetch_add(ONE, Ordering::Acquire); // notice using ONE; a shifted value for the halfreg
-                break;
+                return Some(out);
+            }
+            if op.is_push() {
+                return self.elimination.offer(op, raw_rj, attempts).then(ptr::null_mut);
+            }
+            let taken = self.elimination.take(op);
+            if taken.is_none() {
+                attempts.snooze();
+            }
+            taken
+        });
+        attempts.tally(&self.retries.extents);
+        waits.tally(&self.retries.slots);
+        match reserved {
+            Ok(reserved) => self.finish(op, reserved, raw_rj),
+            Err(out) => {
+                #[cfg(all(feature = "trace", not(loom)))]
+                trace::handoff(&self.leftright);
+                out
             }
         }
     }
 
-    pub fn pop_back(&self) -> Option<Box<T>> {
-        loop {
-            let (sens_left, sens_right) = self.fetch_extent_rel();
-            if sens_left % self.capacity == sens_right % self.capacity {
-                return None;
+    /// Carries out `op` the direct way and nothing else, for a combiner.
+    fn apply(&self, op: Op, raw_rj: *mut Record<T>) -> *mut Record<T> {
+        let (mut attempts, mut waits) = (Attempts::new(&self.backoff), Attempts::new(&self.backoff));
+        let Ok(reserved) = reserve_with(self, op, &mut attempts, |lost, attempts| {
+            match lost {
+                Lost::Extents => attempts.snooze(),
+                Lost::Slot => waits.snooze(),
             }
-            let this_right = sens_right.wrapping_sub(1) % self.capacity;
-            // println!("Trying to pop {this_right}");
-            let rval = self.deque[(this_right % self.capacity) as usize].load(Ordering::Relaxed);
-            if rval & LEFTMASK == 0 {
-                return None;
+            None::<core::convert::Infallible>
+        });
+        attempts.tally(&self.retries.extents);
+        waits.tally(&self.retries.slots);
+        // one snooze per lost attempt
+        #[cfg(feature = "metrics")]
+        {
+            let shard = self.metrics.shard();
+            Shard::add(&shard.extents_lost, attempts.retries() as usize);
+            Shard::add(if op.is_push() { &shard.fill_lost } else { &shard.drain_lost }, waits.retries() as usize);
+        }
+        self.finish(op, reserved, raw_rj)
+    }
+
+    /// Fills or drains the slot `op` reserved, if it got one.
+    fn finish(&self, op: Op, reserved: Option<Reserved>, raw_rj: *mut Record<T>) -> *mut Record<T> {
+        match reserved {
+            Some(reserved) if op.is_push() => {
+                #[cfg(feature = "metrics")]
+                self.metrics.shard().high_water.fetch_max(self.len(), Ordering::Relaxed);
+                self.fill(reserved, raw_rj);
+                ptr::null_mut()
             }
-            let idx = 0;
-            let entry = ((self.op_id.fetch_add(1, Ordering::Relaxed) as usize + 1) << 32) | idx;
-            if let Ok(old_one) = self.deque[(this_right % self.capacity) as usize].compare_exchange(
-                rval,
-                entry,
-                Ordering::Release,
-                Ordering::Relaxed,
-            ) {
-                // println!("Seeking from {}, ok", lval & LEFTMASK);
-                let out = self.backing[(rval & LEFTMASK) as usize]
-                    .0
-                    .swap(Joque::build_raw_null_rj(), Ordering::AcqRel);
-
-                unsafe {
-                    let output = Box::from_raw(out);
-                    if (*output).1.is_null() {
-                        // println!("Well that was weird pop_back couldn't pull {} cause out was null", rval & RIGHTMASK >> 32);
-                        // println!("Here's some other stuff: ");
-                        // println!("Directed pop state: {this_right}");
-                        // println!("Claimed Op ID: {}", entry >> 32);
-                        // println!("Old Cmp Data: {} {}", old_one & RIGHTMASK >> 32, old_one & LEFTMASK);
-                        // panic!("This should not occur");
-                        //return None;
-                        // panic!("definitely should never happen;");
-                        return None;
-                    } else if (*output).0 == ((old_one & RIGHTMASK) >> 32) as u32 {
-                        self.leftright.fetch_sub(ONE, Ordering::Release); // notice using ONE here             
-                        let response = Box::from_raw((*output).1);
-                        return Some(response);
-                    } else {
-                        // println!("Well that was weird pop_back couldn't pull {} cause the thingies no matchy", rval & RIGHTMASK >> 32);
-                        // println!("the thingies are {} and {}", (*out).0, ((old_one & RIGHTMASK) >> 32) as u32);
-                        // println!("Here's some other stuff: ");
-                        // println!("Directed pop state: {this_right}");
-                        // println!("Claimed Op ID: {}", entry >> 32);
-                        // println!("Old Cmp Data: {} {}", old_one & RIGHTMASK >> 32, old_one & LEFTMASK);
-                        panic!("also should never happen;");
-                        println!("Oh shit, oh shit, oh shit, put it back!!");
-                        self.backing[(rval & LEFTMASK) as usize]
-                            .0
-                            .store(out, Ordering::Release);
-                        return None;
-                    }
+            Some(reserved) => self.drain(reserved),
+            // full, so the push keeps its record; empty, so the pop gets none
+            None => raw_rj,
+        }
+    }
+
+    /// Publishes the record holding a pushed item into the slot reserved for it.
+    fn fill(&self, Reserved { slot, val }: Reserved, raw_rj: *mut Record<T>) {
+        let backing_idx = self.claim_backing();
+        let release = self.backing[backing_idx as usize]
+            .0
+            .swap(raw_rj, Ordering::AcqRel);
+        #[cfg(all(feature = "trace", not(loom)))]
+        trace::record(&self.leftright, backing_idx as usize, release, raw_rj);
+        let tag = next_gen(val);
+        // nothing can reach the record until the slot is published, so the tag is still ours to set
+        unsafe { (*raw_rj).0 = tag as Tag };
+        self.publish(slot, val, (tag << HALF) | backing_idx as Word);
+        // only free the old record once the slot is published, so nothing that happens
+        // in here can keep the next operation at this slot waiting
+        Self::release_null_rj(&self.alloc, release);
+    }
+
+    /// Takes the record holding the item out of the slot reserved for a pop. The caller
+    /// frees the record.
+    fn drain(&self, Reserved { slot, val: old_one }: Reserved) -> *mut Record<T> {
+        self.publish(slot, old_one, next_gen(old_one) << HALF);
+        let backing_idx = (old_one & LEFTMASK) as usize;
+        let fresh = Self::build_raw_null_rj(&self.alloc);
+        let out = self.backing[backing_idx].0.swap(fresh, Ordering::AcqRel);
+        #[cfg(all(feature = "trace", not(loom)))]
+        trace::record(&self.leftright, backing_idx, out, fresh);
+        self.release_backing(backing_idx);
+
+        unsafe {
+            debug_assert!((*out).1.is_some(), "slot {slot} pointed at record {backing_idx}, which held no item");
+            if (*out).1.is_some() && (*out).0 != generation(old_one) as Tag {
+                #[cfg(feature = "metrics")]
+                Shard::bump(&self.metrics.shard().tag_mismatches);
+                panic!(
+                    "also should never happen; slot {slot} held tag {} and record {backing_idx}, which was tagged {}\n{}",
+                    generation(old_one),
+                    (*out).0,
+                    Dumped(self)
+                );
+            }
+        }
+        out
+    }
+
+    /// Drops everything still queued, then frees every record, returning how many of those
+    /// still held an item no live slot pointed at. Each step leaves the deque consistent
+    /// before an item is dropped, so it's safe to run again after a destructor panics.
+    fn teardown(&mut self) -> usize {
+        // only an operation in progress parks records in the elimination or request cells
+        debug_assert!(self.elimination.is_idle() && self.combining.is_idle());
+        // everything still queued sits between the extents; pop it like anyone else would
+        while self.pop_front().is_some() {}
+
+        let mut orphans = 0;
+        while let Some(rec) = self.backing.pop() {
+            if Self::release_null_rj(&self.alloc, rec.0.into_inner()).is_some() {
+                orphans += 1;
+            }
+            // drop recordjoque
+        }
+        orphans
+    }
+
+    /// Claims a free backing record: 💡✨ bitwise find empty, a word of records at a time.
+    /// There are four records per ring slot, so a free one turns up unless a crowd of
+    /// pushes is mid-flight.
+    fn claim_backing(&self) -> u32 {
+        let words = self.claimed.len();
+        let start = self.idx.fetch_add(1, Ordering::Relaxed) as usize;
+        let mut attempts = Attempts::new(&self.backoff);
+        loop {
+            for i in 0..words {
+                let word = (start + i) % words;
+                let bits = self.claimed[word].load(Ordering::Relaxed);
+                if bits == usize::MAX {
+                    continue;
                 }
+                let bit = 1 << (!bits).trailing_zeros();
+                if self.claimed[word].fetch_or(bit, Ordering::Acquire) & bit == 0 {
+                    attempts.tally(&self.retries.records);
+                    return (word * BITS) as u32 + bit.trailing_zeros();
+                }
+                #[cfg(feature = "metrics")]
+                Shard::bump(&self.metrics.shard().claim_lost);
             }
+            attempts.snooze();
         }
     }
 
+    /// Hands a backing record back once its item has been swapped out.
+    fn release_backing(&self, backing_idx: usize) {
+        self.claimed[backing_idx / BITS].fetch_and(!(1 << (backing_idx % BITS)), Ordering::Release);
+    }
+
     fn fetch_extent_acq(&self) -> (u32, u32) {
-        let muxed = self.leftright.load(Ordering::Acquire);
-        let left_demuxed = muxed & LEFTMASK;
-        let right_demuxed = (muxed & RIGHTMASK) >> 32;
-        (left_demuxed as u32, right_demuxed as u32)
+        demux(self.leftright.load(Ordering::Acquire))
     }
 
     fn fetch_extent_rel(&self) -> (u32, u32) {
-        let muxed = self.leftright.load(Ordering::Relaxed);
-        let left_demuxed = muxed & LEFTMASK;
-        let right_demuxed = (muxed & RIGHTMASK) >> 32;
-        (left_demuxed as u32, right_demuxed as u32)
+        demux(self.leftright.load(Ordering::Relaxed))
+    }
+
+    /// Number of items between the extents, counting pushes and pops that have
+    /// reserved a slot but not finished. Exact when no other thread is operating,
+    /// otherwise a snapshot that may be stale by the time it's returned.
+    pub fn len(&self) -> usize {
+        let (left, right) = self.fetch_extent_acq();
+        occupancy(left, right, self.capacity)
     }
 
-    pub fn get(&self, _index: usize) -> Option<usize> {
-        None
+    /// Linearizable: a push counts from the moment it reserves its slot, so an
+    /// in-flight push makes the deque non-empty even before its item is visible.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn mutate<F>(&self, _index: usize, _op: F)
-    where
-        F: FnMut(T),
-    {
+    /// Most items the deque is meant to hold at once; one ring slot stays free so the
+    /// extents can't meet.
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize - 1
     }
 
-    pub fn set(&self, _index: usize, _val: usize) {}
+    /// How many more pushes there's currently room for, for admission control.
+    pub fn remaining(&self) -> usize {
+        self.capacity().saturating_sub(self.len())
+    }
 
-    pub fn get_unchecked(&self, _index: usize) -> usize {
-        unimplemented!()
+    /// How often operations on this deque have had to back off and retry so far.
+    pub fn retries(&self) -> Retries {
+        Retries { combined: self.combining.combined(), ..self.retries.snapshot() }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn borrow(&self) -> &Self {
-        &self
+        self
     }
 }
 
+#[cfg(feature = "alloc")]
 mod tests {
     #[allow(unused_imports)]
-    use crate::{Joque, LEFTMASK, RIGHTMASK};
+    use crate::{Joque, Word, HALF, LEFTMASK, RIGHTMASK, genmask};
+    #[cfg(all(test, not(loom)))]
+    use crate::history::{self, Clock, Recorder};
     #[allow(unused_imports)]
-    use std::sync::atomic::Ordering;
+    use core::sync::atomic::Ordering;
 
     #[cfg(not(loom))]
     #[test]
@@ -393,6 +989,268 @@ mod tests {
         }
     }
 
+    #[cfg(not(loom))]
+    #[test]
+    pub fn len_tracks_both_ends() {
+        let deque = Joque::new(25);
+        assert!(deque.is_empty());
+        assert_eq!(24, deque.capacity());
+
+        deque.push_front(Box::new(1));
+        deque.push_back(Box::new(2));
+        deque.push_back(Box::new(3));
+        assert_eq!(3, deque.len());
+        assert_eq!(21, deque.remaining());
+
+        deque.pop_back();
+        deque.pop_front();
+        assert_eq!(1, deque.len());
+        assert!(!deque.is_empty());
+
+        deque.pop_front();
+        assert!(deque.is_empty());
+        assert!(deque.pop_front().is_none());
+        assert!(deque.pop_back().is_none());
+        assert_eq!(0, deque.len());
+        assert_eq!(24, deque.remaining());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    pub fn len_across_wrap() {
+        let deque = Joque::new(7);
+
+        // walk the extents around the ring a couple of times in both directions
+        for i in 0..8 {
+            deque.push_back(Box::new(i));
+            deque.push_back(Box::new(i));
+            assert_eq!(2, deque.len());
+            assert_eq!(i, *deque.pop_front().unwrap());
+            assert_eq!(i, *deque.pop_front().unwrap());
+            assert!(deque.is_empty());
+        }
+        for i in 0..8 {
+            deque.push_front(Box::new(i));
+            assert_eq!(1, deque.len());
+            assert_eq!(i, *deque.pop_back().unwrap());
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    pub fn unsized_items() {
+        let deque: Joque<str> = Joque::new(8);
+        deque.push_back("squirpy".into());
+        deque.push_front("squirp".into());
+        assert_eq!("squirpy", &*deque.pop_back().unwrap());
+        assert_eq!("squirp", &*deque.pop_back().unwrap());
+
+        let jobs: Joque<dyn FnOnce() -> usize + Send> = Joque::new(8);
+        let captured = String::from("oogah");
+        jobs.push_back(Box::new(|| 1));
+        jobs.push_back(Box::new(move || captured.len()));
+        assert_eq!(1, jobs.pop_front().unwrap()());
+        assert_eq!(5, jobs.pop_front().unwrap()());
+    }
+
+    #[cfg(all(not(loom), not(miri)))]
+    #[test]
+    pub fn unsized_jobs_across_threads() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let jobs: Arc<Joque<dyn FnOnce() + Send>> = Arc::new(Joque::new(64));
+        for _ in 0..32 {
+            let ran = ran.clone();
+            jobs.push_back(Box::new(move || {
+                ran.fetch_add(1, Ordering::Relaxed);
+            }));
+        }
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let jobs = jobs.clone();
+                std::thread::spawn(move || {
+                    while let Some(job) = jobs.pop_front() {
+                        job();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(32, ran.load(Ordering::Relaxed));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    pub fn full_rejects_push() {
+        let deque = Joque::new(6);
+
+        for i in 0..deque.capacity() {
+            assert!(deque.try_push_back(Box::new(i)).is_ok());
+        }
+        assert_eq!(0, deque.remaining());
+        assert_eq!(Err(Box::new(99)), deque.try_push_front(Box::new(99)));
+        assert_eq!(Err(Box::new(99)), deque.try_push_back(Box::new(99)));
+
+        assert_eq!(0, *deque.pop_front().unwrap());
+        assert_eq!(1, deque.remaining());
+        assert!(deque.try_push_front(Box::new(7)).is_ok());
+        assert_eq!(7, *deque.pop_front().unwrap());
+        assert_eq!(4, *deque.pop_back().unwrap());
+    }
+
+    #[cfg(all(feature = "std", not(loom), not(miri)))]
+    #[test]
+    pub fn push_waits_for_room() {
+        let deque = Joque::new(6);
+        for i in 0..deque.capacity() {
+            deque.push_back(Box::new(i));
+        }
+        std::thread::scope(|s| {
+            let pusher = s.spawn(|| deque.push_front(Box::new(99)));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            assert!(!pusher.is_finished());
+            assert_eq!(4, *deque.pop_back().unwrap());
+            pusher.join().unwrap();
+        });
+        assert_eq!(99, *deque.pop_front().unwrap());
+        assert_eq!(deque.capacity() - 1, deque.len());
+    }
+
+    #[cfg(all(feature = "std", not(loom), not(miri)))]
+    #[test]
+    pub fn every_backoff_under_contention() {
+        use crate::{Backoff, Exponential, ParkAfter, Retries, Spin};
+        use std::sync::Arc;
+
+        fn run<B: Backoff + Send + Sync + 'static>(backoff: B) {
+            let deque = Arc::new(Joque::with_backoff(64, backoff));
+            deque.push_back(Box::new(0));
+            assert_eq!(Some(0), deque.pop_front().map(|item| *item));
+            // nobody to lose a CAS to
+            assert_eq!(Retries::default(), deque.retries());
+
+            let ths: Vec<_> = (0..8)
+                .map(|t| {
+                    let deque = deque.clone();
+                    std::thread::spawn(move || {
+                        let mut sum = 0;
+                        for i in 0..500 {
+                            if t % 2 == 0 {
+                                deque.push_front(Box::new(i));
+                                sum += *deque.pop_back().unwrap();
+                            } else {
+                                deque.push_back(Box::new(i));
+                                sum += *deque.pop_front().unwrap();
+                            }
+                        }
+                        sum
+                    })
+                })
+                .collect();
+            let total: usize = ths.into_iter().map(|th| th.join().unwrap()).sum();
+            assert_eq!(8 * (0..500).sum::<usize>(), total);
+            assert!(deque.is_empty());
+        }
+
+        run(Spin);
+        run(Exponential::default());
+        run(crate::Yield);
+        run(ParkAfter::default());
+    }
+
+    #[cfg(all(not(loom), not(miri)))]
+    #[test]
+    pub fn eliminating_stack_loses_nothing() {
+        use std::sync::Arc;
+
+        let deque = Arc::new(Joque::with_backoff(64, crate::Spin).eliminating(4));
+        let ths: Vec<_> = (0..8u32)
+            .map(|t| {
+                let deque = deque.clone();
+                std::thread::spawn(move || {
+                    let mut popped = vec![];
+                    for i in 0..1000 {
+                        deque.push_front(Box::new(t * 1000 + i));
+                        popped.push(*deque.pop_front().unwrap());
+                    }
+                    popped
+                })
+            })
+            .collect();
+        let mut seen: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        seen.sort();
+        assert_eq!((0..8000).collect::<Vec<_>>(), seen);
+        assert!(deque.is_empty());
+    }
+
+    #[cfg(all(feature = "std", not(loom), not(miri)))]
+    #[test]
+    pub fn combining_loses_nothing() {
+        use std::sync::Arc;
+
+        let deque = Arc::new(Joque::with_backoff(4096, crate::Spin).combining(8));
+        let ths: Vec<_> = (0..8u32)
+            .map(|t| {
+                let deque = deque.clone();
+                std::thread::spawn(move || {
+                    let mut popped = vec![];
+                    for i in 0..1000 {
+                        let item = Box::new(t * 1000 + i);
+                        match (t + i) % 4 {
+                            0 => deque.push_front(item),
+                            1 => deque.push_back(item),
+                            _ if t % 2 == 0 => {
+                                deque.push_front(item);
+                                popped.extend(deque.pop_back().map(|item| *item));
+                            }
+                            _ => {
+                                deque.push_back(item);
+                                popped.extend(deque.pop_front().map(|item| *item));
+                            }
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        let mut seen: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        while let Some(item) = deque.pop_front() {
+            seen.push(*item);
+        }
+        seen.sort();
+        assert_eq!((0..8000).collect::<Vec<_>>(), seen);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    pub fn tags_are_per_slot() {
+        let deque = Joque::new(8);
+        let generation = |slot: usize| deque.deque[slot].load(Ordering::SeqCst) >> HALF;
+
+        for i in 0..5 {
+            deque.push_front(Box::new(i));
+            assert_eq!(i, *deque.pop_front().unwrap());
+        }
+        // only the slot the front end kept landing on has aged
+        assert_eq!(10, (0..deque.deque.len()).map(generation).sum::<Word>());
+        assert_eq!(1, (0..deque.deque.len()).filter(|&slot| generation(slot) != 0).count());
+
+        // a generation that wraps round to zero still reads as empty and still matches its record
+        for slot in &deque.deque {
+            let _ = slot.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |_| Some(genmask() << HALF));
+        }
+        for i in 0..20 {
+            deque.push_back(Box::new(i));
+            assert_eq!(i, *deque.pop_front().unwrap());
+        }
+        assert!(deque.is_empty());
+    }
+
 
 
     #[cfg(all(loom, not(miri)))]
@@ -432,6 +1290,54 @@ mod tests {
         });
     }
 
+    #[cfg(all(loom, not(miri)))]
+    #[test]
+    fn eliminating_push_pop_pairs() {
+        use loom::sync::Arc;
+        use loom::thread;
+
+        loom::model(|| {
+            let deque = Arc::new(Joque::new(8).eliminating(1));
+            let pusher = {
+                let deque = deque.clone();
+                thread::spawn(move || deque.push_front(Box::new(1)))
+            };
+            let popped = deque.pop_front().map(|item| *item);
+            pusher.join().unwrap();
+            let rest = deque.pop_front().map(|item| *item);
+            // the one item comes out exactly once, whether through the ring or the array
+            assert_eq!(Some(1), popped.or(rest));
+            assert!(popped.is_none() || rest.is_none());
+        });
+    }
+
+    #[cfg(all(loom, not(miri)))]
+    #[test]
+    fn combining_pushes_and_pops() {
+        use loom::sync::Arc;
+        use loom::thread;
+
+        loom::model(|| {
+            let deque = Arc::new(Joque::new(8).combining(2));
+            let pusher = {
+                let deque = deque.clone();
+                thread::spawn(move || {
+                    deque.push_back(Box::new(1));
+                    deque.push_front(Box::new(2));
+                })
+            };
+            let popped = deque.pop_back().map(|item| *item);
+            pusher.join().unwrap();
+            let mut rest = vec![];
+            while let Some(item) = deque.pop_back() {
+                rest.push(*item);
+            }
+            rest.extend(popped);
+            rest.sort();
+            assert_eq!(vec![1, 2], rest);
+        });
+    }
+
     #[test]
     #[cfg(all(not(loom), not(miri)))]
     fn interleaved_modification() {
@@ -445,48 +1351,59 @@ mod tests {
         for _rerun in 0..RERUNS {
             // println!("~~~~~ {rerun} ~~~~~");
             let deque = std::sync::Arc::new(Joque::new(WIDTH));
+            let clock = std::sync::Arc::new(Clock::default());
 
             for _ in 0..PAD_WIDTH {
                 deque.push_front(Box::new(u32::MAX));
             }
 
             let mut ths: Vec<_> = (0..THREAD_COUNT / 2)
-                .map(|idx| {
+                .map(|t| {
                     let big_deque = deque.clone();
+                    let clock = clock.clone();
 
                     std::thread::spawn(move || {
-                        big_deque.push_front(Box::new(idx));
-                        let _ = big_deque.pop_front().is_none();
-                        big_deque.push_front(Box::new(idx + 1));
-                        big_deque.push_front(Box::new(idx + 2));
+                        let mut rec = Recorder::new(&clock);
+                        rec.push_front(&big_deque, t * 4);
+                        rec.pop_front(&big_deque);
+                        rec.push_front(&big_deque, t * 4 + 1);
+                        rec.push_front(&big_deque, t * 4 + 2);
+                        rec.log
                     })
                 })
                 .collect();
 
             ths.append(
-                &mut (0..THREAD_COUNT / 2)
-                    .map(|idx| {
+                &mut (THREAD_COUNT / 2..THREAD_COUNT)
+                    .map(|t| {
                         let big_deque = deque.clone();
+                        let clock = clock.clone();
 
                         std::thread::spawn(move || {
-                            big_deque.push_front(Box::new(idx));
-                            big_deque.push_front(Box::new(idx + 1));
-                            let _ = big_deque.pop_front().is_none();
-                            big_deque.push_front(Box::new(idx + 2));
+                            let mut rec = Recorder::new(&clock);
+                            rec.push_front(&big_deque, t * 4);
+                            rec.push_front(&big_deque, t * 4 + 1);
+                            rec.pop_front(&big_deque);
+                            rec.push_front(&big_deque, t * 4 + 2);
+                            rec.log
                         })
                     })
                     .collect(),
             );
 
-            for th in ths {
-                th.join().unwrap();
-            }
+            let mut history: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+            let extents = deque.leftright.load(Ordering::Relaxed);
+            // drain what's left into the history too, so an item lost or duplicated inside shows up
+            let mut rec = Recorder::new(&clock);
+            while rec.pop_front(&deque).is_some() {}
+            history.extend(rec.log);
+            history::check(&history).unwrap();
             // despite the fact that each thread should contribute net +1 push into the listing,
             // there's a stochastic event, which may occur, such that all threads behaving this way simultaneously
             // observe and empty stack when popping, and so there's a chance of 'failed' pops.
             assert!(
                 LEFT_START - THREAD_COUNT * 2 - PAD_WIDTH
-                    >= (deque.clone().leftright.load(Ordering::Relaxed) & LEFTMASK) as u32
+                    >= (extents & LEFTMASK) as u32
             );
         }
     }
@@ -505,50 +1422,60 @@ mod tests {
         for _rerun in 0..RERUNS {
             // println!("~~~~~ {rerun} ~~~~~");
             let deque = std::sync::Arc::new(Joque::new(WIDTH));
+            let clock = std::sync::Arc::new(Clock::default());
 
             for _ in 0..PAD_WIDTH {
                 deque.push_back(Box::new(u32::MAX));
             }
 
             let mut ths: Vec<_> = (0..THREAD_COUNT / 2)
-                .map(|idx| {
+                .map(|t| {
                     let big_deque = deque.clone();
+                    let clock = clock.clone();
 
                     std::thread::spawn(move || {
-                        big_deque.push_back(Box::new(idx));
-                        let out = big_deque.pop_back().is_none() as i32;
-                        big_deque.push_back(Box::new(idx + 1));
-                        big_deque.push_back(Box::new(idx + 2));
-                        out
+                        let mut rec = Recorder::new(&clock);
+                        rec.push_back(&big_deque, t * 4);
+                        rec.pop_back(&big_deque);
+                        rec.push_back(&big_deque, t * 4 + 1);
+                        rec.push_back(&big_deque, t * 4 + 2);
+                        rec.log
                     })
                 })
                 .collect();
 
             ths.append(
-                &mut (0..THREAD_COUNT / 2)
-                    .map(|idx| {
+                &mut (THREAD_COUNT / 2..THREAD_COUNT)
+                    .map(|t| {
                         let big_deque = deque.clone();
+                        let clock = clock.clone();
 
                         std::thread::spawn(move || {
-                            big_deque.push_back(Box::new(idx));
-                            big_deque.push_back(Box::new(idx + 1));
-                            let out = big_deque.pop_back().is_none() as i32;
-                            big_deque.push_back(Box::new(idx + 2));
-                            out
+                            let mut rec = Recorder::new(&clock);
+                            rec.push_back(&big_deque, t * 4);
+                            rec.push_back(&big_deque, t * 4 + 1);
+                            rec.pop_back(&big_deque);
+                            rec.push_back(&big_deque, t * 4 + 2);
+                            rec.log
                         })
                     })
                     .collect(),
             );
 
-            let _weridness_score = ths.into_iter().map(|th| th.join().unwrap()).sum::<i32>();
-            // println!("Weirdness: {weridness_score}");
+            let mut history: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+            let extents = deque.leftright.load(Ordering::Relaxed);
+            // drain what's left into the history too, so an item lost or duplicated inside shows up
+            let mut rec = Recorder::new(&clock);
+            while rec.pop_front(&deque).is_some() {}
+            history.extend(rec.log);
+            history::check(&history).unwrap();
             // println!("Expected {} found {}", RIGHT_START + THREAD_COUNT*2 + PAD_WIDTH, deque.clone().leftright.load(Ordering::Relaxed) & RIGHTMASK >> 32);
             // despite the fact that each thread should contribute net +1 push into the listing,
             // there's a stochastic event, which may occur, such that all threads behaving this way simultaneously
             // observe and empty stack when popping, and so there's a chance of 'failed' pops.
             assert!(
                 RIGHT_START + THREAD_COUNT * 2 + PAD_WIDTH
-                    <= ((deque.clone().leftright.load(Ordering::Relaxed) & RIGHTMASK) >> 32) as u32
+                    <= ((extents & RIGHTMASK) >> HALF) as u32
             );
         }
     }
@@ -566,48 +1493,59 @@ mod tests {
         for _rerun in 0..RERUNS {
             // println!("~~~~~ {rerun} ~~~~~");
             let deque = std::sync::Arc::new(Joque::new(WIDTH));
+            let clock = std::sync::Arc::new(Clock::default());
 
             for _ in 0..PAD_WIDTH {
                 deque.push_front(Box::new(u32::MAX));
             }
 
             let mut ths: Vec<_> = (0..THREAD_COUNT / 2)
-                .map(|idx| {
+                .map(|t| {
                     let big_deque = deque.clone();
+                    let clock = clock.clone();
 
                     std::thread::spawn(move || {
-                        big_deque.push_front(Box::new(idx));
-                        let _ = big_deque.pop_front().is_none();
-                        big_deque.push_front(Box::new(idx + 1));
-                        big_deque.push_front(Box::new(idx + 2));
+                        let mut rec = Recorder::new(&clock);
+                        rec.push_front(&big_deque, t * 4);
+                        rec.pop_front(&big_deque);
+                        rec.push_front(&big_deque, t * 4 + 1);
+                        rec.push_front(&big_deque, t * 4 + 2);
+                        rec.log
                     })
                 })
                 .collect();
 
             ths.append(
-                &mut (0..THREAD_COUNT / 2)
-                    .map(|idx| {
+                &mut (THREAD_COUNT / 2..THREAD_COUNT)
+                    .map(|t| {
                         let big_deque = deque.clone();
+                        let clock = clock.clone();
 
                         std::thread::spawn(move || {
-                            big_deque.push_front(Box::new(idx));
-                            big_deque.push_front(Box::new(idx + 1));
-                            let _ = big_deque.pop_front().is_none();
-                            big_deque.push_front(Box::new(idx + 2));
+                            let mut rec = Recorder::new(&clock);
+                            rec.push_front(&big_deque, t * 4);
+                            rec.push_front(&big_deque, t * 4 + 1);
+                            rec.pop_front(&big_deque);
+                            rec.push_front(&big_deque, t * 4 + 2);
+                            rec.log
                         })
                     })
                     .collect(),
             );
 
-            for th in ths {
-                th.join().unwrap();
-            }
+            let mut history: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+            let extents = deque.leftright.load(Ordering::Relaxed);
+            // drain what's left into the history too, so an item lost or duplicated inside shows up
+            let mut rec = Recorder::new(&clock);
+            while rec.pop_front(&deque).is_some() {}
+            history.extend(rec.log);
+            history::check(&history).unwrap();
             // despite the fact that each thread should contribute net +1 push into the listing,
             // there's a stochastic event, which may occur, such that all threads behaving this way simultaneously
             // observe and empty stack when popping, and so there's a chance of 'failed' pops.
             assert!(
                 LEFT_START - THREAD_COUNT * 2 - PAD_WIDTH
-                    >= (deque.clone().leftright.load(Ordering::Relaxed) & LEFTMASK) as u32
+                    >= (extents & LEFTMASK) as u32
             );
         }
     }
@@ -626,50 +1564,60 @@ mod tests {
         for _rerun in 0..RERUNS {
             // println!("~~~~~ {rerun} ~~~~~");
             let deque = std::sync::Arc::new(Joque::new(WIDTH));
+            let clock = std::sync::Arc::new(Clock::default());
 
             for _ in 0..PAD_WIDTH {
                 deque.push_back(Box::new(u32::MAX));
             }
 
             let mut ths: Vec<_> = (0..THREAD_COUNT / 2)
-                .map(|idx| {
+                .map(|t| {
                     let big_deque = deque.clone();
+                    let clock = clock.clone();
 
                     std::thread::spawn(move || {
-                        big_deque.push_back(Box::new(idx));
-                        let out = big_deque.pop_back().is_none() as i32;
-                        big_deque.push_back(Box::new(idx + 1));
-                        big_deque.push_back(Box::new(idx + 2));
-                        out
+                        let mut rec = Recorder::new(&clock);
+                        rec.push_back(&big_deque, t * 4);
+                        rec.pop_back(&big_deque);
+                        rec.push_back(&big_deque, t * 4 + 1);
+                        rec.push_back(&big_deque, t * 4 + 2);
+                        rec.log
                     })
                 })
                 .collect();
 
             ths.append(
-                &mut (0..THREAD_COUNT / 2)
-                    .map(|idx| {
+                &mut (THREAD_COUNT / 2..THREAD_COUNT)
+                    .map(|t| {
                         let big_deque = deque.clone();
+                        let clock = clock.clone();
 
                         std::thread::spawn(move || {
-                            big_deque.push_back(Box::new(idx));
-                            big_deque.push_back(Box::new(idx + 1));
-                            let out = big_deque.pop_back().is_none() as i32;
-                            big_deque.push_back(Box::new(idx + 2));
-                            out
+                            let mut rec = Recorder::new(&clock);
+                            rec.push_back(&big_deque, t * 4);
+                            rec.push_back(&big_deque, t * 4 + 1);
+                            rec.pop_back(&big_deque);
+                            rec.push_back(&big_deque, t * 4 + 2);
+                            rec.log
                         })
                     })
                     .collect(),
             );
 
-            let _weridness_score = ths.into_iter().map(|th| th.join().unwrap()).sum::<i32>();
-            // println!("Weirdness: {weridness_score}");
+            let mut history: Vec<_> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+            let extents = deque.leftright.load(Ordering::Relaxed);
+            // drain what's left into the history too, so an item lost or duplicated inside shows up
+            let mut rec = Recorder::new(&clock);
+            while rec.pop_front(&deque).is_some() {}
+            history.extend(rec.log);
+            history::check(&history).unwrap();
             // println!("Expected {} found {}", RIGHT_START + THREAD_COUNT*2 + PAD_WIDTH, deque.clone().leftright.load(Ordering::Relaxed) & RIGHTMASK >> 32);
             // despite the fact that each thread should contribute net +1 push into the listing,
             // there's a stochastic event, which may occur, such that all threads behaving this way simultaneously
             // observe and empty stack when popping, and so there's a chance of 'failed' pops.
             assert!(
                 RIGHT_START + THREAD_COUNT * 2 + PAD_WIDTH
-                    <= ((deque.clone().leftright.load(Ordering::Relaxed) & RIGHTMASK) >> 32) as u32
+                    <= ((extents & RIGHTMASK) >> HALF) as u32
             );
         }
     }
@@ -0,0 +1,224 @@
+//! Counting what a [`Joque`] does, without the counting becoming contention of its own.
+//!
+//! With the `metrics` feature each deque carries sixteen cache-line-sized blocks of
+//! counters, and each thread always bumps the same block, picked once per thread. Threads
+//! only share a block once there are more of them than blocks, so the counting stays off
+//! the lines every operation fights over. [`Joque::stats`] adds the blocks up into a
+//! [`JoqueStats`].
+//!
+//! The counters are plain `core` atomics on purpose: loom and the `fault-injection` feature
+//! only get to see the atomics that make up the deque itself.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
+
+use crate::pad::CachePadded;
+use crate::{Backoff, Joque, RecordAllocator};
+
+/// Counter blocks per deque.
+pub(crate) const SHARDS: usize = 16;
+
+/// What a deque has done over its lifetime; see [`Joque::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JoqueStats {
+    /// Pushes that got in, by end.
+    pub pushed_front: usize,
+    pub pushed_back: usize,
+    /// Pops that came back with an item, by end.
+    pub popped_front: usize,
+    pub popped_back: usize,
+    /// Pushes turned away because the ring was full.
+    pub full: usize,
+    /// Pops that came back empty.
+    pub empty: usize,
+    /// CASes on the extents that lost to another operation.
+    pub extents_lost: usize,
+    /// Times a push found its ring slot still held by another operation, or lost the CAS
+    /// marking it.
+    pub fill_lost: usize,
+    /// The same for pops.
+    pub drain_lost: usize,
+    /// Attempts to claim a backing record that another push got to first.
+    pub claim_lost: usize,
+    /// Pops that reserved a slot and drained it, only to find a record with no item, and so
+    /// went round again. Debug builds panic instead.
+    pub spurious_none: usize,
+    /// Pops whose record was tagged for a different generation of the slot than the one
+    /// they drained.
+    pub tag_mismatches: usize,
+    /// Most items the deque has held at once, counting reserved slots as held.
+    pub high_water: usize,
+}
+
+/// One thread's block of counters.
+#[derive(Default)]
+pub(crate) struct Shard {
+    pub(crate) pushed_front: AtomicUsize,
+    pub(crate) pushed_back: AtomicUsize,
+    pub(crate) popped_front: AtomicUsize,
+    pub(crate) popped_back: AtomicUsize,
+    pub(crate) full: AtomicUsize,
+    pub(crate) empty: AtomicUsize,
+    pub(crate) extents_lost: AtomicUsize,
+    pub(crate) fill_lost: AtomicUsize,
+    pub(crate) drain_lost: AtomicUsize,
+    pub(crate) claim_lost: AtomicUsize,
+    pub(crate) spurious_none: AtomicUsize,
+    pub(crate) tag_mismatches: AtomicUsize,
+    pub(crate) high_water: AtomicUsize,
+}
+
+impl Shard {
+    /// Adds `n` to `counter`. Only this thread, and whichever others were handed the same block,
+    /// ever writes it, so a relaxed add is all it takes.
+    pub(crate) fn add(counter: &AtomicUsize, n: usize) {
+        if n != 0 {
+            counter.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn bump(counter: &AtomicUsize) {
+        Shard::add(counter, 1);
+    }
+}
+
+pub(crate) struct Metrics(Vec<CachePadded<Shard>>);
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Metrics((0..SHARDS).map(|_| CachePadded::new(Shard::default())).collect())
+    }
+
+    /// The calling thread's block.
+    pub(crate) fn shard(&self) -> &Shard {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        std::thread_local! {
+            static PICKED: Cell<Option<usize>> = const { Cell::new(None) };
+        }
+        let picked = PICKED
+            .try_with(|picked| match picked.get() {
+                Some(shard) => shard,
+                None => {
+                    let shard = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+                    picked.set(Some(shard));
+                    shard
+                }
+            })
+            // a thread on its way out shares the first block
+            .unwrap_or(0);
+        &self.0[picked]
+    }
+
+    fn snapshot(&self) -> JoqueStats {
+        let mut stats = JoqueStats::default();
+        for shard in &self.0 {
+            let read = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+            stats.pushed_front += read(&shard.pushed_front);
+            stats.pushed_back += read(&shard.pushed_back);
+            stats.popped_front += read(&shard.popped_front);
+            stats.popped_back += read(&shard.popped_back);
+            stats.full += read(&shard.full);
+            stats.empty += read(&shard.empty);
+            stats.extents_lost += read(&shard.extents_lost);
+            stats.fill_lost += read(&shard.fill_lost);
+            stats.drain_lost += read(&shard.drain_lost);
+            stats.claim_lost += read(&shard.claim_lost);
+            stats.spurious_none += read(&shard.spurious_none);
+            stats.tag_mismatches += read(&shard.tag_mismatches);
+            stats.high_water = stats.high_water.max(read(&shard.high_water));
+        }
+        stats
+    }
+}
+
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> Joque<T, A, B> {
+    /// Adds up every thread's counters. Each counter is read on its own, so with operations
+    /// in flight the totals can be a few operations apart from each other.
+    pub fn stats(&self) -> JoqueStats {
+        self.metrics.snapshot()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_every_end_and_edge() {
+        let deque = Joque::new(5);
+        deque.push_back(Box::new(1));
+        deque.push_back(Box::new(2));
+        deque.push_front(Box::new(0));
+        deque.push_front(Box::new(-1));
+        assert!(deque.try_push_back(Box::new(3)).is_err());
+        while deque.pop_back().is_some() {}
+        deque.pop_front();
+
+        let stats = deque.stats();
+        assert_eq!((2, 2), (stats.pushed_front, stats.pushed_back));
+        assert_eq!((0, 4), (stats.popped_front, stats.popped_back));
+        assert_eq!((1, 2), (stats.full, stats.empty));
+        assert_eq!(4, stats.high_water);
+        assert_eq!(0, stats.extents_lost + stats.fill_lost + stats.drain_lost + stats.claim_lost);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "held no item"))]
+    fn pops_of_an_emptied_record_are_spurious() {
+        let deque = Joque::new(6);
+        deque.push_back(Box::new(1));
+        deque.push_back(Box::new(2));
+        let (left, _) = crate::demux(deque.leftright.load(Ordering::Relaxed));
+        let record = (deque.deque[left as usize + 1].load(Ordering::Relaxed) & crate::LEFTMASK) as usize;
+        // take the item out from under the slot, as a broken push might have left it
+        drop(unsafe { (*deque.backing[record].0.load(Ordering::Relaxed)).1.take() });
+
+        assert_eq!(Some(2), deque.pop_front().map(|item| *item));
+        assert_eq!(None, deque.pop_front());
+        let stats = deque.stats();
+        assert_eq!((1, 1, 1), (stats.spurious_none, stats.empty, stats.popped_front));
+    }
+
+    // the record the panicking pop took is never freed
+    #[cfg(not(miri))]
+    #[test]
+    fn tag_mismatches_are_counted_before_the_panic() {
+        let deque = Joque::new(6);
+        deque.push_back(Box::new(1));
+        let (left, _) = crate::demux(deque.leftright.load(Ordering::Relaxed));
+        let record = (deque.deque[left as usize + 1].load(Ordering::Relaxed) & crate::LEFTMASK) as usize;
+        unsafe { (*deque.backing[record].0.load(Ordering::Relaxed)).0 += 7 };
+
+        let popped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| deque.pop_front()));
+        assert!(popped.is_err());
+        assert_eq!(1, deque.stats().tag_mismatches);
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn threads_add_up_across_shards() {
+        let deque = Joque::new(64);
+        std::thread::scope(|s| {
+            for t in 0..(SHARDS + 4) {
+                let deque = &deque;
+                s.spawn(move || {
+                    for i in 0..100 {
+                        if (t + i) % 2 == 0 {
+                            let _ = deque.try_push_back(Box::new(i));
+                        } else {
+                            deque.pop_front();
+                        }
+                    }
+                });
+            }
+        });
+        let stats = deque.stats();
+        let pushes = stats.pushed_back + stats.full;
+        let pops = stats.popped_front + stats.empty;
+        assert_eq!((SHARDS + 4) * 100, pushes + pops);
+        assert_eq!(stats.pushed_back - stats.popped_front, deque.len());
+        assert!(stats.high_water <= deque.capacity());
+    }
+}
@@ -0,0 +1,228 @@
+//! Random operation sequences run against a `Joque` and a `VecDeque` side by side.
+//!
+//! A sequence is a list of [`Step`]s, generated from a seed by the in-crate [`Rng`] so a
+//! failure can be replayed from its seed alone. The generator drifts between stretches that
+//! mostly push and stretches that mostly pop, so small rings keep filling up, draining out,
+//! and walking their extents round the wrap. [`run`] plays a sequence on both deques and
+//! stops at the first step where they disagree; [`shrink`] then cuts a failing sequence down
+//! to one where removing any single step makes the failure go away.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::vec::Vec;
+
+use crate::Joque;
+
+/// Xorshift64*, which is plenty for picking operations and keeps the tests free of deps.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift sticks at zero, and nearby seeds should still diverge quickly
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform enough in `0..n` for small `n`.
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+/// One operation, and for the queries, what the deque has to agree on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    PushFront(u32),
+    PushBack(u32),
+    PopFront,
+    PopBack,
+    Len,
+    Remaining,
+}
+
+/// Where a run first went wrong.
+pub(crate) struct Mismatch {
+    at: usize,
+    step: Step,
+    expected: Option<u32>,
+    got: Option<u32>,
+}
+
+impl fmt::Debug for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} ({:?}): expected {:?}, got {:?}", self.at, self.step, self.expected, self.got)
+    }
+}
+
+/// `len` steps for a deque of `capacity` items, with values numbered from 0.
+pub(crate) fn generate(rng: &mut Rng, capacity: usize, len: usize) -> Vec<Step> {
+    let mut steps = Vec::with_capacity(len);
+    let mut value = 0;
+    // out of 8 steps, how many push; redrawn now and then to swing between full and empty
+    let mut pushes = 4;
+    let mut held = 0usize;
+    while steps.len() < len {
+        if rng.below(2 * capacity as u64 + 2) == 0 {
+            pushes = 1 + rng.below(7);
+        }
+        let step = match rng.below(16) {
+            0 => Step::Len,
+            1 => Step::Remaining,
+            roll if (roll - 2) % 8 < pushes => {
+                value += 1;
+                if rng.below(2) == 0 { Step::PushFront(value) } else { Step::PushBack(value) }
+            }
+            _ => {
+                if rng.below(2) == 0 { Step::PopFront } else { Step::PopBack }
+            }
+        };
+        // only a rough count, for keeping pushes past full from dominating
+        match step {
+            Step::PushFront(_) | Step::PushBack(_) if held > capacity => continue,
+            Step::PushFront(_) | Step::PushBack(_) => held += 1,
+            Step::PopFront | Step::PopBack => held = held.saturating_sub(1),
+            _ => {}
+        }
+        steps.push(step);
+    }
+    steps
+}
+
+/// Plays `steps` on a fresh `Joque::new(width)` and on a `VecDeque` held to the same
+/// capacity, comparing every pop, every rejected push, and every query. Panics if the
+/// deque's structure doesn't [validate](Joque::validate) afterwards.
+pub(crate) fn run(width: u32, steps: &[Step]) -> Result<(), Mismatch> {
+    let mut deque = Joque::new(width);
+    let capacity = deque.capacity();
+    let mut spec = VecDeque::with_capacity(capacity);
+    for (at, &step) in steps.iter().enumerate() {
+        let (expected, got) = match step {
+            Step::PushFront(item) | Step::PushBack(item) => {
+                let rejected = (spec.len() == capacity).then_some(item);
+                if rejected.is_none() {
+                    if let Step::PushFront(_) = step { spec.push_front(item) } else { spec.push_back(item) }
+                }
+                let pushed = if let Step::PushFront(_) = step {
+                    deque.try_push_front(Box::new(item))
+                } else {
+                    deque.try_push_back(Box::new(item))
+                };
+                (rejected, pushed.err().map(|item| *item))
+            }
+            Step::PopFront => (spec.pop_front(), deque.pop_front().map(|item| *item)),
+            Step::PopBack => (spec.pop_back(), deque.pop_back().map(|item| *item)),
+            Step::Len => (Some(spec.len() as u32), Some(deque.len() as u32)),
+            Step::Remaining => (Some((capacity - spec.len()) as u32), Some(deque.remaining() as u32)),
+        };
+        if expected != got {
+            return Err(Mismatch { at, step, expected, got });
+        }
+    }
+    let report = deque.validate();
+    assert!(report.is_ok(), "{report}");
+    Ok(())
+}
+
+/// Cuts `steps` down while `fails` still holds: first everything after the first failing
+/// step, then ever smaller chunks, down to single steps, until no one step can go.
+pub(crate) fn shrink(mut steps: Vec<Step>, fails: impl Fn(&[Step]) -> Option<usize>) -> Vec<Step> {
+    let Some(at) = fails(&steps) else {
+        return steps;
+    };
+    steps.truncate(at + 1);
+    let mut chunk = steps.len().div_ceil(2);
+    while chunk > 0 {
+        let mut start = 0;
+        while start < steps.len() {
+            let mut fewer = steps.clone();
+            fewer.drain(start..(start + chunk).min(steps.len()));
+            match fails(&fewer) {
+                Some(at) => {
+                    fewer.truncate(at + 1);
+                    steps = fewer;
+                }
+                None => start += chunk,
+            }
+        }
+        chunk /= 2;
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seeds to run, or just `JOQUE_SEED` to replay one.
+    fn seeds(count: u64) -> Vec<u64> {
+        match std::env::var("JOQUE_SEED") {
+            Ok(seed) => vec![seed.parse().expect("JOQUE_SEED is a u64")],
+            Err(_) => (0..count).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_vecdeque() {
+        for seed in seeds(if cfg!(miri) { 2 } else { 200 }) {
+            let mut rng = Rng::new(seed);
+            // small rings wrap and fill often; the odd wide one keeps long runs honest
+            let width = if rng.below(8) == 0 { 64 } else { 5 + rng.below(8) as u32 };
+            let steps = generate(&mut rng, width as usize - 1, if cfg!(miri) { 200 } else { 2000 });
+            if let Err(mismatch) = run(width, &steps) {
+                let minimal = shrink(steps, |steps| run(width, steps).err().map(|m| m.at));
+                panic!(
+                    "seed {seed}, Joque::new({width}): {mismatch:?}\nminimal: {minimal:?}\n{:?}",
+                    run(width, &minimal).unwrap_err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_reach_the_edges() {
+        let mut rng = Rng::new(7);
+        let (width, capacity) = (6, 5);
+        let steps = generate(&mut rng, capacity, 2000);
+        let (mut held, mut full, mut empty) = (0usize, 0, 0);
+        for step in &steps {
+            match step {
+                Step::PushFront(_) | Step::PushBack(_) if held == capacity => full += 1,
+                Step::PushFront(_) | Step::PushBack(_) => held += 1,
+                Step::PopFront | Step::PopBack if held == 0 => empty += 1,
+                Step::PopFront | Step::PopBack => held -= 1,
+                _ => {}
+            }
+        }
+        assert!(full > 10 && empty > 10, "{full} pushes when full, {empty} pops when empty");
+        run(width, &steps).unwrap();
+    }
+
+    #[test]
+    fn shrinks_to_the_culprit() {
+        // pretend a pop from the back is wrong whenever two pushes to the front preceded it
+        let fails = |steps: &[Step]| {
+            let mut fronts = 0;
+            steps.iter().position(|&step| {
+                fronts += (step == Step::PushFront(0)) as usize;
+                step == Step::PopBack && fronts >= 2
+            })
+        };
+        let mut rng = Rng::new(1);
+        let mut steps: Vec<_> = generate(&mut rng, 8, 300)
+            .into_iter()
+            .map(|step| if let Step::PushFront(_) = step { Step::PushFront(0) } else { step })
+            .collect();
+        steps.push(Step::PushFront(0));
+        steps.push(Step::PushFront(0));
+        steps.push(Step::PopBack);
+
+        let minimal = shrink(steps, fails);
+        assert_eq!(vec![Step::PushFront(0), Step::PushFront(0), Step::PopBack], minimal);
+    }
+}
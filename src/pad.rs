@@ -0,0 +1,70 @@
+//! Keeping the hot atomics off each other's cache lines.
+//!
+//! Every push and pop CASes `leftright`, and pushes also bump the claim cursor, so when
+//! those share a line every operation invalidates it for every other core twice over. Neighbouring ring slots have the same problem at a smaller scale; the
+//! `padded-slots` feature gives each slot a line of its own, at the cost of the ring taking
+//! 128 bytes per slot instead of 8.
+
+use core::ops::Deref;
+
+#[allow(unused_imports)]
+use crate::sync::AtomicWord;
+
+/// `T`, aligned and padded out to a cache line. That's 128 bytes on x86_64, whose spatial
+/// prefetcher pulls lines in pairs, and on aarch64 and powerpc64, whose big cores have
+/// 128-byte lines; 64 elsewhere.
+#[cfg_attr(
+    any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "powerpc64"),
+    repr(align(128))
+)]
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "powerpc64")),
+    repr(align(64))
+)]
+pub(crate) struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub(crate) const fn new(val: T) -> Self {
+        CachePadded(val)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// One ring slot of the heap-backed deques.
+#[cfg(all(feature = "alloc", feature = "padded-slots"))]
+pub(crate) type Slot = CachePadded<AtomicWord>;
+
+#[cfg(all(feature = "alloc", not(feature = "padded-slots")))]
+pub(crate) type Slot = AtomicWord;
+
+#[cfg(all(feature = "alloc", feature = "padded-slots"))]
+pub(crate) fn empty_slot() -> Slot {
+    CachePadded::new(AtomicWord::new(0))
+}
+
+#[cfg(all(feature = "alloc", not(feature = "padded-slots")))]
+pub(crate) fn empty_slot() -> Slot {
+    AtomicWord::new(0)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[cfg(not(loom))]
+    #[test]
+    fn padding_separates_neighbours() {
+        let pair = [CachePadded::new(AtomicWord::new(0)), CachePadded::new(AtomicWord::new(0))];
+        let gap = &*pair[1] as *const AtomicWord as usize - &*pair[0] as *const AtomicWord as usize;
+        assert!(gap >= 64);
+        assert_eq!(0, &*pair[0] as *const AtomicWord as usize % 64);
+    }
+}
@@ -0,0 +1,162 @@
+//! Where [`Joque`](crate::Joque) gets its backing records.
+//!
+//! Every push and pop swaps a freshly allocated record into the backing table and frees the
+//! one it displaced, so on a busy deque records are most of the allocator traffic. They are
+//! all one size per `T`, which makes them a good fit for a slab or arena; a
+//! [`RecordAllocator`] passed to [`Joque::new_in`](crate::Joque::new_in) serves them.
+//! `std::alloc::Allocator` would do the same job but isn't stable.
+//!
+//! Items still arrive and leave in the caller's `Box`es, and the ring and claim bitmap are
+//! allocated once, up front, from the global allocator.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// A source of backing records.
+///
+/// # Safety
+///
+/// `allocate` must hand back memory valid for `layout` that stays put until it's passed to
+/// `deallocate` with the same layout. Records are allocated on whichever thread pushes or
+/// pops and may be freed on another, so an implementation shared across threads must
+/// tolerate that.
+pub unsafe trait RecordAllocator {
+    /// Allocates a block for one record. Never returns on failure; calling
+    /// [`alloc::alloc::handle_alloc_error`] is the usual way out.
+    fn allocate(&self, layout: Layout) -> NonNull<u8>;
+
+    /// # Safety
+    ///
+    /// `ptr` came from `allocate` on this allocator with this `layout` and hasn't been freed.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global allocator, which is what [`Joque::new`](crate::Joque::new) uses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+unsafe impl RecordAllocator for Global {
+    fn allocate(&self, layout: Layout) -> NonNull<u8> {
+        // records always hold at least a tag, so `layout` is never zero-sized
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        NonNull::new(ptr).unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) }
+    }
+}
+
+/// Lets several deques draw from one arena.
+unsafe impl<A: RecordAllocator + ?Sized> RecordAllocator for &A {
+    fn allocate(&self, layout: Layout) -> NonNull<u8> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { (**self).deallocate(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    /// A free list of record-sized blocks carved from the global allocator, counting
+    /// what's outstanding.
+    #[cfg(not(loom))]
+    struct Slab {
+        free: std::sync::Mutex<Vec<usize>>,
+        outstanding: std::sync::atomic::AtomicUsize,
+        fresh: std::sync::atomic::AtomicUsize,
+    }
+
+    #[cfg(not(loom))]
+    impl Slab {
+        fn new() -> Self {
+            Slab {
+                free: std::sync::Mutex::new(vec![]),
+                outstanding: std::sync::atomic::AtomicUsize::new(0),
+                fresh: std::sync::atomic::AtomicUsize::new(0),
+            }
+        }
+
+        fn outstanding(&self) -> usize {
+            self.outstanding.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[cfg(not(loom))]
+    unsafe impl RecordAllocator for Slab {
+        fn allocate(&self, layout: Layout) -> NonNull<u8> {
+            self.outstanding.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            match self.free.lock().unwrap().pop() {
+                Some(addr) => NonNull::new(addr as *mut u8).unwrap(),
+                None => {
+                    self.fresh.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Global.allocate(layout)
+                }
+            }
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+            self.outstanding.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            self.free.lock().unwrap().push(ptr.as_ptr() as usize);
+        }
+    }
+
+    #[cfg(not(loom))]
+    impl Drop for Slab {
+        fn drop(&mut self) {
+            // every block here is a record of the one `T` the tests use
+            let layout = Layout::new::<crate::Record<i32>>();
+            for addr in self.free.get_mut().unwrap().drain(..) {
+                unsafe { Global.deallocate(NonNull::new(addr as *mut u8).unwrap(), layout) };
+            }
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn records_come_from_the_slab() {
+        let slab = Slab::new();
+        let deque = crate::Joque::new_in(8, &slab);
+        let blank = slab.outstanding();
+        assert_eq!(8 * 4, blank);
+
+        for i in 0..100 {
+            deque.push_front(Box::new(i));
+            deque.push_back(Box::new(-i));
+            assert_eq!(Some(i), deque.pop_front().map(|item| *item));
+            assert_eq!(Some(-i), deque.pop_back().map(|item| *item));
+        }
+        // one record swapped in for each one swapped out, recycled rather than grown
+        assert_eq!(blank, slab.outstanding());
+        assert!(slab.fresh.load(std::sync::atomic::Ordering::SeqCst) < blank + 4);
+
+        deque.push_back(Box::new(7));
+        drop(deque);
+        assert_eq!(0, slab.outstanding());
+    }
+
+    #[cfg(all(not(loom), not(miri)))]
+    #[test]
+    fn slab_shared_across_threads() {
+        let slab = Slab::new();
+        let deque = crate::Joque::new_in(64, &slab);
+        std::thread::scope(|s| {
+            let deque = &deque;
+            for t in 0..8 {
+                s.spawn(move || {
+                    for i in 0..200 {
+                        deque.push_back(Box::new(t * 1000 + i));
+                        assert!(deque.pop_front().is_some());
+                    }
+                });
+            }
+        });
+        drop(deque);
+        assert_eq!(0, slab.outstanding());
+    }
+}
@@ -0,0 +1,681 @@
+//! Recording what every thread did to the deques, and playing it back one step at a time.
+//!
+//! With the `trace` feature, each extent CAS, slot CAS, and backing record swap a `Joque`
+//! makes is logged as an [`Event`], along with the start and end of every push and pop.
+//! Each thread logs into a ring of its own, so logging takes no lock and never waits on
+//! another thread; once a ring is full its oldest events make way. [`events`] gathers every
+//! ring into one list in the order the events were stamped, [`dump`] writes that list out
+//! as text, one event per line, and [`dump_on_panic`] has it written wherever a panic
+//! happens, failed asserts included.
+//!
+//! [`replay`] takes such a list, from [`events`] or from [`load`]ing a dump, and serializes
+//! it: it puts the operations in the order they moved the extents, runs them one by one on
+//! a fresh deque, and stops at the first one that comes out differently, say a pop that
+//! got some other push's item than the one the trace says it drained.
+//!
+//! A deque is told apart from others by the address of its extents, so one that moves in
+//! between operations shows up as two. Logging makes every step slower, which stretches
+//! how long operations overlap; the 64-thread history tests in `lib.rs` can take the
+//! linearizability checker a very long time with it on, so leave them to untraced runs.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+use std::sync::{Arc, Mutex, Once};
+use std::vec::Vec;
+
+use crate::sync::{AtomicWord, Word};
+use crate::{Joque, Op};
+
+/// The operation an event was part of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    PushFront,
+    PopFront,
+    PushBack,
+    PopBack,
+}
+
+impl Operation {
+    fn is_push(self) -> bool {
+        matches!(self, Operation::PushFront | Operation::PushBack)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::PushFront => "push_front",
+            Operation::PopFront => "pop_front",
+            Operation::PushBack => "push_back",
+            Operation::PopBack => "pop_back",
+        }
+    }
+}
+
+impl From<Op> for Operation {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::PushFront => Operation::PushFront,
+            Op::PopFront => Operation::PopFront,
+            Op::PushBack => Operation::PushBack,
+            Op::PopBack => Operation::PopBack,
+        }
+    }
+}
+
+/// What happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// An operation started on a deque whose ring has `slot` slots.
+    Begin(Operation),
+    /// A CAS on the extents from `before` to `after`, reserving `slot` if it went through.
+    Extents { ok: bool },
+    /// The extents read `before`, which left no slot to reserve: full or empty.
+    Refused,
+    /// A CAS on ring slot `slot` from `before` to `after`.
+    Slot { ok: bool },
+    /// Backing record `slot` swapped from the record at `before` to the one at `after`.
+    Record,
+    /// The operation was finished through the elimination array or a combiner.
+    Handoff,
+    /// The operation finished; `ok` is whether a push got in or a pop got an item.
+    End { ok: bool },
+}
+
+/// One logged step. `thread` and `op` say whose step it was: the `op`th operation its
+/// thread started while tracing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub seq: u64,
+    pub thread: u32,
+    pub op: u32,
+    pub deque: usize,
+    pub kind: Kind,
+    pub slot: u32,
+    pub before: u128,
+    pub after: u128,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Begin(operation) => operation.name(),
+            Kind::Extents { ok: true } => "extents",
+            Kind::Extents { ok: false } => "extents_lost",
+            Kind::Refused => "refused",
+            Kind::Slot { ok: true } => "slot",
+            Kind::Slot { ok: false } => "slot_lost",
+            Kind::Record => "record",
+            Kind::Handoff => "handoff",
+            Kind::End { ok: true } => "end",
+            Kind::End { ok: false } => "end_none",
+        };
+        write!(
+            f,
+            "{} {} {} {:x} {kind} {} {:x} {:x}",
+            self.seq, self.thread, self.op, self.deque, self.slot, self.before, self.after
+        )
+    }
+}
+
+impl Event {
+    fn parse(line: &str) -> Option<Event> {
+        let mut fields = line.split_whitespace();
+        let mut next = || fields.next();
+        let seq = next()?.parse().ok()?;
+        let thread = next()?.parse().ok()?;
+        let op = next()?.parse().ok()?;
+        let deque = usize::from_str_radix(next()?, 16).ok()?;
+        let kind = match next()? {
+            "push_front" => Kind::Begin(Operation::PushFront),
+            "pop_front" => Kind::Begin(Operation::PopFront),
+            "push_back" => Kind::Begin(Operation::PushBack),
+            "pop_back" => Kind::Begin(Operation::PopBack),
+            "extents" => Kind::Extents { ok: true },
+            "extents_lost" => Kind::Extents { ok: false },
+            "refused" => Kind::Refused,
+            "slot" => Kind::Slot { ok: true },
+            "slot_lost" => Kind::Slot { ok: false },
+            "record" => Kind::Record,
+            "handoff" => Kind::Handoff,
+            "end" => Kind::End { ok: true },
+            "end_none" => Kind::End { ok: false },
+            _ => return None,
+        };
+        let slot = next()?.parse().ok()?;
+        let before = u128::from_str_radix(next()?, 16).ok()?;
+        let after = u128::from_str_radix(next()?, 16).ok()?;
+        Some(Event { seq, thread, op, deque, kind, slot, before, after })
+    }
+
+    // packed into the eight words of a ring cell, `seq` first
+    fn pack(&self) -> [u64; 8] {
+        let (kind, flag) = match self.kind {
+            Kind::Begin(operation) => (0, operation as u64),
+            Kind::Extents { ok } => (1, ok as u64),
+            Kind::Refused => (2, 0),
+            Kind::Slot { ok } => (3, ok as u64),
+            Kind::Record => (4, 0),
+            Kind::Handoff => (5, 0),
+            Kind::End { ok } => (6, ok as u64),
+        };
+        [
+            self.seq,
+            self.thread as u64 | (self.op as u64) << 32,
+            self.deque as u64,
+            kind | flag << 8 | (self.slot as u64) << 32,
+            self.before as u64,
+            (self.before >> 64) as u64,
+            self.after as u64,
+            (self.after >> 64) as u64,
+        ]
+    }
+
+    fn unpack(words: [u64; 8]) -> Event {
+        const OPERATIONS: [Operation; 4] =
+            [Operation::PushFront, Operation::PopFront, Operation::PushBack, Operation::PopBack];
+        let flag = words[3] >> 8 & 0xff;
+        let kind = match words[3] & 0xff {
+            0 => Kind::Begin(OPERATIONS[flag as usize]),
+            1 => Kind::Extents { ok: flag != 0 },
+            2 => Kind::Refused,
+            3 => Kind::Slot { ok: flag != 0 },
+            4 => Kind::Record,
+            5 => Kind::Handoff,
+            _ => Kind::End { ok: flag != 0 },
+        };
+        Event {
+            seq: words[0],
+            thread: words[1] as u32,
+            op: (words[1] >> 32) as u32,
+            deque: words[2] as usize,
+            kind,
+            slot: (words[3] >> 32) as u32,
+            before: words[4] as u128 | (words[5] as u128) << 64,
+            after: words[6] as u128 | (words[7] as u128) << 64,
+        }
+    }
+}
+
+/// Events each thread keeps before its oldest start making way.
+pub const RING: usize = 1 << 12;
+
+/// One thread's events. Only that thread writes, and each cell is a seqlock so a reader
+/// gathering events can tell a cell it caught half-written.
+struct Ring {
+    next: AtomicU64,
+    cells: Vec<[AtomicU64; 8]>,
+}
+
+impl Ring {
+    fn new() -> Self {
+        Ring { next: AtomicU64::new(0), cells: (0..RING).map(|_| Default::default()).collect() }
+    }
+
+    fn push(&self, event: &Event) {
+        let at = self.next.load(Ordering::Relaxed);
+        let cell = &self.cells[at as usize % RING];
+        cell[0].store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        let words = event.pack();
+        for (word, value) in cell.iter().zip(words).skip(1) {
+            word.store(value, Ordering::Relaxed);
+        }
+        cell[0].store(words[0], Ordering::Release);
+        self.next.store(at + 1, Ordering::Relaxed);
+    }
+
+    fn read(&self, into: &mut Vec<Event>) {
+        for cell in &self.cells {
+            let seq = cell[0].load(Ordering::Acquire);
+            let mut words = [seq; 8];
+            for (value, word) in words.iter_mut().zip(cell).skip(1) {
+                *value = word.load(Ordering::Relaxed);
+            }
+            fence(Ordering::Acquire);
+            // zero is unwritten or mid-write; a changed seq means it was overwritten meanwhile
+            if seq != 0 && cell[0].load(Ordering::Relaxed) == seq {
+                into.push(Event::unpack(words));
+            }
+        }
+    }
+}
+
+static SEQ: AtomicU64 = AtomicU64::new(1);
+static THREADS: AtomicU32 = AtomicU32::new(0);
+
+/// Every ring there is, and the ones whose threads have exited, for new threads to reuse.
+struct Rings {
+    all: Vec<Arc<Ring>>,
+    free: Vec<Arc<Ring>>,
+}
+
+static RINGS: Mutex<Rings> = Mutex::new(Rings { all: Vec::new(), free: Vec::new() });
+
+struct Local {
+    thread: u32,
+    ops: core::cell::Cell<u32>,
+    ring: Arc<Ring>,
+}
+
+impl Local {
+    fn new() -> Self {
+        let mut rings = RINGS.lock().unwrap();
+        let ring = rings.free.pop().unwrap_or_else(|| {
+            let ring = Arc::new(Ring::new());
+            rings.all.push(ring.clone());
+            ring
+        });
+        Local { thread: THREADS.fetch_add(1, Ordering::Relaxed), ops: Default::default(), ring }
+    }
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        if let Ok(mut rings) = RINGS.lock() {
+            rings.free.push(self.ring.clone());
+        }
+    }
+}
+
+std::thread_local! {
+    static LOCAL: Local = Local::new();
+}
+
+fn log(extents: &AtomicWord, kind: Kind, slot: u32, before: u128, after: u128) {
+    let _ = LOCAL.try_with(|local| {
+        if let Kind::Begin(_) = kind {
+            local.ops.set(local.ops.get() + 1);
+        }
+        local.ring.push(&Event {
+            seq: SEQ.fetch_add(1, Ordering::SeqCst),
+            thread: local.thread,
+            op: local.ops.get(),
+            deque: extents as *const AtomicWord as usize,
+            kind,
+            slot,
+            before,
+            after,
+        });
+    });
+}
+
+pub(crate) fn begin(extents: &AtomicWord, op: Op, capacity: u32) {
+    log(extents, Kind::Begin(op.into()), capacity, 0, 0);
+}
+
+pub(crate) fn extents(extents: &AtomicWord, slot: u32, before: Word, after: Word, ok: bool) {
+    log(extents, Kind::Extents { ok }, slot, before as u128, after as u128);
+}
+
+pub(crate) fn refused(extents: &AtomicWord, seen: Word) {
+    log(extents, Kind::Refused, 0, seen as u128, seen as u128);
+}
+
+pub(crate) fn slot(extents: &AtomicWord, slot: u32, before: Word, after: Word, ok: bool) {
+    log(extents, Kind::Slot { ok }, slot, before as u128, after as u128);
+}
+
+pub(crate) fn record<R>(extents: &AtomicWord, backing_idx: usize, before: *mut R, after: *mut R) {
+    log(extents, Kind::Record, backing_idx as u32, before as usize as u128, after as usize as u128);
+}
+
+pub(crate) fn handoff(extents: &AtomicWord) {
+    log(extents, Kind::Handoff, 0, 0, 0);
+}
+
+pub(crate) fn end(extents: &AtomicWord, ok: bool) {
+    log(extents, Kind::End { ok }, 0, 0, 0);
+}
+
+/// The stamp the next event will get; events from here on have a `seq` at least this.
+pub fn now() -> u64 {
+    SEQ.load(Ordering::SeqCst)
+}
+
+/// Every event still in any thread's ring, in stamp order.
+pub fn events() -> Vec<Event> {
+    let rings = RINGS.lock().map(|rings| rings.all.clone()).unwrap_or_default();
+    let mut events = Vec::new();
+    for ring in &rings {
+        ring.read(&mut events);
+    }
+    events.sort_unstable_by_key(|event| event.seq);
+    events
+}
+
+/// Writes [`events`] to `path`, one per line.
+pub fn dump(path: &Path) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    for event in events() {
+        writeln!(out, "{event}")?;
+    }
+    out.flush()
+}
+
+/// Reads back what [`dump`] wrote.
+pub fn load(path: &Path) -> io::Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for line in io::BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        let event = Event::parse(&line)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("not an event: {line}")))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Where a panic dumps the trace, once [`dump_on_panic`] has installed the hook.
+static PANIC_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Has every panic from here on [`dump`] to `path` before the panic message goes out. Only
+/// the first call installs the hook; later calls just move where it writes.
+pub fn dump_on_panic(path: impl Into<PathBuf>) {
+    static HOOK: Once = Once::new();
+    *PANIC_PATH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(path.into());
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(std::boxed::Box::new(move |info| {
+            dump_for_panic();
+            previous(info)
+        }));
+    });
+}
+
+/// What the hook [`dump_on_panic`] installs does before handing the panic on.
+fn dump_for_panic() {
+    let path = PANIC_PATH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    if let Some(path) = path
+        && let Err(err) = dump(&path)
+    {
+        std::eprintln!("couldn't dump the trace to {}: {err}", path.display());
+    }
+}
+
+/// Where [`replay`] parted ways with the trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// No operation of any deque was traced.
+    Empty,
+    /// The trace doesn't start from a fresh deque, or has a hole in it; the rings probably
+    /// wrapped.
+    Incomplete { seq: u64 },
+    /// Some operation went through the elimination array or a combiner, which replay
+    /// doesn't model.
+    Handoff { seq: u64 },
+    /// The extents came out of the operation at `seq` differently.
+    Extents { seq: u64, traced: u128, replayed: u128 },
+    /// The operation at `seq` had a different outcome.
+    Outcome { seq: u64, thread: u32, op: u32, traced: Outcome, replayed: Outcome },
+}
+
+/// How one operation came out. Items are named by the (thread, op) that pushed them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pushed,
+    Full,
+    Popped { thread: u32, op: u32 },
+    Empty,
+    /// A pop whose item the trace lost track of.
+    PoppedUnknown,
+}
+
+/// Serializes the trace of the first deque in `events` and replays it on a fresh deque of
+/// the same width, checking the extents after every operation and what every operation
+/// got. Returns how many operations replayed.
+pub fn replay(events: &[Event]) -> Result<usize, Divergence> {
+    let Some(first) = events.iter().find(|event| matches!(event.kind, Kind::Begin(_))) else {
+        return Err(Divergence::Empty);
+    };
+    let (deque, width) = (first.deque, first.slot);
+    let events: Vec<&Event> = events.iter().filter(|event| event.deque == deque).collect();
+    if let Some(handoff) = events.iter().find(|event| event.kind == Kind::Handoff) {
+        return Err(Divergence::Handoff { seq: handoff.seq });
+    }
+
+    // what each operation is, and whose item each record held
+    let mut operations = HashMap::new();
+    let mut holder = HashMap::new();
+    let mut popped = HashMap::new();
+    for event in &events {
+        let who = (event.thread, event.op);
+        match event.kind {
+            Kind::Begin(operation) => {
+                operations.insert(who, operation);
+            }
+            Kind::Record if operations.get(&who).is_some_and(|operation| operation.is_push()) => {
+                holder.insert(event.after, who);
+            }
+            Kind::Record => {
+                let pusher = holder.remove(&event.before);
+                popped.insert(who, pusher);
+            }
+            _ => {}
+        }
+    }
+
+    // the operations in the order they moved or read the extents. Stamps are taken just
+    // after the CAS, so two that raced can come out swapped; the chain of extent values
+    // puts them back in order.
+    let decisions: Vec<&Event> = events
+        .iter()
+        .copied()
+        .filter(|event| matches!(event.kind, Kind::Extents { ok: true } | Kind::Refused))
+        .collect();
+    let replayed = Joque::<(u32, u32)>::new(width);
+    let mut extents = replayed.leftright.load(Ordering::Relaxed) as u128;
+    let mut done = vec![false; decisions.len()];
+    let mut count = 0;
+    for i in 0..decisions.len() {
+        if done[i] {
+            continue;
+        }
+        // a few stamps either side is as far as a race can swap them
+        let Some(j) = (i..decisions.len().min(i + 64)).find(|&j| !done[j] && decisions[j].before == extents)
+        else {
+            return Err(Divergence::Incomplete { seq: decisions[i].seq });
+        };
+        done[j] = true;
+        let event = decisions[j];
+        let who = (event.thread, event.op);
+        let Some(&operation) = operations.get(&who) else {
+            return Err(Divergence::Incomplete { seq: event.seq });
+        };
+        let outcome = match operation {
+            Operation::PushFront => replayed.try_push_front(std::boxed::Box::new(who)),
+            Operation::PushBack => replayed.try_push_back(std::boxed::Box::new(who)),
+            Operation::PopFront | Operation::PopBack => {
+                let item = if operation == Operation::PopFront {
+                    replayed.pop_front()
+                } else {
+                    replayed.pop_back()
+                };
+                let replayed = item.map_or(Outcome::Empty, |item| Outcome::Popped { thread: item.0, op: item.1 });
+                let traced = match (event.kind, popped.get(&who)) {
+                    (Kind::Refused, _) => Outcome::Empty,
+                    (_, Some(Some((thread, op)))) => Outcome::Popped { thread: *thread, op: *op },
+                    _ => Outcome::PoppedUnknown,
+                };
+                if traced != replayed && traced != Outcome::PoppedUnknown {
+                    return Err(Divergence::Outcome { seq: event.seq, thread: who.0, op: who.1, traced, replayed });
+                }
+                Ok(())
+            }
+        };
+        if operation.is_push() {
+            let traced = if event.kind == Kind::Refused { Outcome::Full } else { Outcome::Pushed };
+            let replayed = if outcome.is_ok() { Outcome::Pushed } else { Outcome::Full };
+            if traced != replayed {
+                return Err(Divergence::Outcome { seq: event.seq, thread: who.0, op: who.1, traced, replayed });
+            }
+        }
+        extents = replayed.leftright.load(Ordering::Relaxed) as u128;
+        if extents != event.after {
+            return Err(Divergence::Extents { seq: event.seq, traced: event.after, replayed: extents });
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The events since `seq`, for a test that only wants its own.
+pub fn events_since(seq: u64) -> Vec<Event> {
+    let mut events = events();
+    events.retain(|event| event.seq >= seq);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This test's events for `deque`, leaving out whatever other tests were up to.
+    fn ours<T>(mark: u64, deque: &Joque<T>) -> Vec<Event> {
+        let id = &deque.leftright as *const _ as usize;
+        events_since(mark).into_iter().filter(|event| event.deque == id).collect()
+    }
+
+    fn begins(events: &[Event]) -> usize {
+        events.iter().filter(|event| matches!(event.kind, Kind::Begin(_))).count()
+    }
+
+    /// Pushes and pops enough to wrap a small ring, fill it, and empty it.
+    fn workload(deque: &Joque<u32>) {
+        for i in 0..20 {
+            let _ = deque.try_push_back(Box::new(i));
+            if i % 3 == 0 {
+                deque.pop_front();
+            }
+        }
+        while deque.pop_back().is_some() {}
+        deque.pop_front();
+    }
+
+    #[test]
+    fn single_thread_trace_replays() {
+        let mark = now();
+        let deque = Joque::new(7);
+        workload(&deque);
+        let events = ours(mark, &deque);
+        assert!(events.iter().any(|event| event.kind == Kind::Refused));
+        assert_eq!(Ok(begins(&events)), replay(&events));
+    }
+
+    #[test]
+    fn teardown_runs_once() {
+        let mark = now();
+        // boxed so it's still at the same address while it's dropped
+        let deque = Box::new(Joque::new(7));
+        deque.push_back(Box::new(1));
+        deque.push_back(Box::new(2));
+        let thread = ours(mark, &deque)[0].thread;
+        let id = &deque.leftright as *const _ as usize;
+        drop(deque);
+        let events: Vec<_> =
+            events_since(mark).into_iter().filter(|event| event.deque == id && event.thread == thread).collect();
+        // the two pushes, then teardown's two pops and the one that finds it empty
+        assert_eq!(5, begins(&events));
+    }
+
+    #[test]
+    fn replay_catches_a_pop_that_got_the_wrong_item() {
+        let mark = now();
+        let deque = Joque::new(8);
+        deque.push_back(Box::new(1));
+        deque.push_back(Box::new(2));
+        deque.pop_front();
+        deque.pop_front();
+        let mut events = ours(mark, &deque);
+        let ops: Vec<u32> = events.iter().filter(|e| matches!(e.kind, Kind::Begin(_))).map(|e| e.op).collect();
+
+        // pretend the two pops drained each other's records
+        let drains: Vec<usize> =
+            (0..events.len()).filter(|&i| events[i].kind == Kind::Record && events[i].op >= ops[2]).collect();
+        let (first, second) = (drains[0], drains[1]);
+        let swapped = events[first].before;
+        events[first].before = events[second].before;
+        events[second].before = swapped;
+
+        let thread = events[0].thread;
+        assert_eq!(
+            Err(Divergence::Outcome {
+                seq: events.iter().find(|e| e.op == ops[2] && e.kind == Kind::Extents { ok: true }).unwrap().seq,
+                thread,
+                op: ops[2],
+                traced: Outcome::Popped { thread, op: ops[1] },
+                replayed: Outcome::Popped { thread, op: ops[0] },
+            }),
+            replay(&events)
+        );
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn racing_pushes_then_racing_pops_replay() {
+        let mark = now();
+        let deque = Joque::new(256);
+        for pushing in [true, false] {
+            std::thread::scope(|s| {
+                for t in 0..4 {
+                    let deque = &deque;
+                    s.spawn(move || {
+                        for i in 0..50 {
+                            if pushing {
+                                deque.push_back(Box::new(t * 50 + i));
+                            } else {
+                                deque.pop_front();
+                            }
+                        }
+                    });
+                }
+            });
+        }
+        assert_eq!(Ok(400), replay(&ours(mark, &deque)));
+    }
+
+    #[test]
+    fn replay_wants_the_whole_trace() {
+        let mark = now();
+        let deque = Joque::new(7);
+        workload(&deque);
+        let events = ours(mark, &deque);
+        let cut = events.iter().position(|e| e.kind == Kind::Extents { ok: true }).unwrap() + 1;
+        assert!(matches!(replay(&events[cut..]), Err(Divergence::Incomplete { .. })));
+        assert_eq!(Err(Divergence::Empty), replay(&[]));
+    }
+
+    /// A file in the temp dir that's gone again once the test is over, however it ends.
+    struct Scratch(PathBuf);
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    // goes through `dump_for_panic` rather than the hook, which would stay installed for
+    // every other test in the process and dump on each of their panics
+    #[cfg(not(miri))]
+    #[test]
+    fn dumps_round_trip_and_panics_dump() {
+        let scratch = Scratch(std::env::temp_dir().join(format!("joque-trace-{}.txt", std::process::id())));
+        let path = &scratch.0;
+        let mark = now();
+        let deque = Joque::new(7);
+        workload(&deque);
+        dump(path).unwrap();
+        let id = &deque.leftright as *const _ as usize;
+        let loaded: Vec<_> = load(path).unwrap().into_iter().filter(|e| e.seq >= mark && e.deque == id).collect();
+        assert_eq!(ours(mark, &deque), loaded);
+        fs::remove_file(path).unwrap();
+
+        deque.push_front(Box::new(9));
+        *PANIC_PATH.lock().unwrap() = Some(path.clone());
+        dump_for_panic();
+        *PANIC_PATH.lock().unwrap() = None;
+        let dumped = load(path).unwrap();
+        assert!(dumped.iter().any(|e| e.deque == id && e.kind == Kind::Begin(Operation::PushFront)));
+    }
+}
@@ -0,0 +1,269 @@
+//! Checking a quiescent [`Joque`]'s ring, records, and claim bitmap against each other.
+//!
+//! With nothing in flight, the structure is fully determined: every slot strictly between
+//! the extents points at its own backing record, tagged with the slot's generation and
+//! holding an item; every slot outside them is empty; no slot is still marked as held by
+//! an operation; every other record holds nothing; and
+//! a record is marked claimed exactly when a live slot points at it. [`Joque::validate`]
+//! walks all of that and reports each place it doesn't hold.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::sync::*;
+use crate::{BITS, Backoff, Joque, LEFTMASK, MARK, RecordAllocator, demux, generation, wrap_inc};
+
+/// One way a deque's structure was found broken. Slots are ring positions; records are
+/// indices into the backing table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A slot between the extents points at no record.
+    EmptyLiveSlot { slot: u32 },
+    /// A slot is still marked as held by an operation, though none is in flight.
+    MarkedSlot { slot: u32 },
+    /// A slot outside the extents still points at a record.
+    StaleSlot { slot: u32, record: usize },
+    /// A slot points past the end of the backing table.
+    OutOfRange { slot: u32, record: usize },
+    /// A live slot's generation and the tag on its record disagree.
+    TagMismatch { slot: u32, record: usize, slot_tag: u64, record_tag: u64 },
+    /// A live slot's record holds no item.
+    MissingItem { slot: u32, record: usize },
+    /// Two live slots point at the same record.
+    SharedRecord { record: usize, slots: (u32, u32) },
+    /// A record no live slot points at still holds an item.
+    StrayItem { record: usize },
+    /// A record's claim bit is set without a live slot pointing at it, or the other way round.
+    ClaimMismatch { record: usize, claimed: bool },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::EmptyLiveSlot { slot } => write!(f, "slot {slot} is between the extents but empty"),
+            Violation::MarkedSlot { slot } => write!(f, "slot {slot} is still marked as held"),
+            Violation::StaleSlot { slot, record } => {
+                write!(f, "slot {slot} is outside the extents but points at record {record}")
+            }
+            Violation::OutOfRange { slot, record } => {
+                write!(f, "slot {slot} points at record {record}, past the end of the backing table")
+            }
+            Violation::TagMismatch { slot, record, slot_tag, record_tag } => {
+                write!(f, "slot {slot} has generation {slot_tag} but its record {record} is tagged {record_tag}")
+            }
+            Violation::MissingItem { slot, record } => write!(f, "slot {slot}'s record {record} holds no item"),
+            Violation::SharedRecord { record, slots: (a, b) } => {
+                write!(f, "slots {a} and {b} both point at record {record}")
+            }
+            Violation::StrayItem { record } => write!(f, "record {record} holds an item no slot points at"),
+            Violation::ClaimMismatch { record, claimed: true } => {
+                write!(f, "record {record} is claimed but no slot points at it")
+            }
+            Violation::ClaimMismatch { record, claimed: false } => {
+                write!(f, "record {record} is pointed at but not claimed")
+            }
+        }
+    }
+}
+
+/// What [`Joque::validate`] found: the extents it walked between and every violation, in
+/// ring order and then record order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub left: u32,
+    pub right: u32,
+    /// Slots strictly between the extents.
+    pub live: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} live slots between {} and {}", self.live, self.left, self.right)?;
+        if self.is_ok() {
+            return write!(f, ", all consistent");
+        }
+        write!(f, ", {} violations:", self.violations.len())?;
+        for violation in &self.violations {
+            write!(f, "\n  {violation}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ?Sized, A: RecordAllocator, B: Backoff> Joque<T, A, B> {
+    /// Cross-checks the ring, the backing records, and the claim bitmap; see [`Report`].
+    ///
+    /// Takes `&mut self` because the checks only hold with no operation in flight, and
+    /// reading records another thread might free wouldn't be sound. A shared deque can be
+    /// checked once its threads are joined, through `Arc::get_mut`.
+    pub fn validate(&mut self) -> Report {
+        let (left, right) = demux(self.leftright.load(Ordering::Acquire));
+        let mut violations = Vec::new();
+        // which live slot points at each record, if any
+        let mut owner: Vec<Option<u32>> = (0..self.backing.len()).map(|_| None).collect();
+
+        let mut live = 0;
+        let mut slot = wrap_inc(left, self.capacity);
+        while slot != right {
+            live += 1;
+            let val = self.deque[slot as usize].load(Ordering::Acquire);
+            let record = (val & LEFTMASK) as usize;
+            let slot_tag = generation(val) as u64;
+            if val & MARK != 0 {
+                violations.push(Violation::MarkedSlot { slot });
+            }
+            if record == 0 {
+                violations.push(Violation::EmptyLiveSlot { slot });
+            } else if record >= self.backing.len() {
+                violations.push(Violation::OutOfRange { slot, record });
+            } else if let Some(first) = owner[record] {
+                violations.push(Violation::SharedRecord { record, slots: (first, slot) });
+            } else {
+                owner[record] = Some(slot);
+                // nothing is in flight, so nothing can free the record under us
+                let (tag, item) = unsafe { &*self.backing[record].0.load(Ordering::Acquire) };
+                if *tag as u64 != slot_tag {
+                    violations.push(Violation::TagMismatch { slot, record, slot_tag, record_tag: *tag as u64 });
+                }
+                if item.is_none() {
+                    violations.push(Violation::MissingItem { slot, record });
+                }
+            }
+            slot = wrap_inc(slot, self.capacity);
+        }
+        while slot != wrap_inc(left, self.capacity) {
+            let val = self.deque[slot as usize].load(Ordering::Acquire);
+            if val & MARK != 0 {
+                violations.push(Violation::MarkedSlot { slot });
+            }
+            let record = (val & LEFTMASK) as usize;
+            if record != 0 {
+                violations.push(Violation::StaleSlot { slot, record });
+            }
+            slot = wrap_inc(slot, self.capacity);
+        }
+
+        for (record, owner) in owner.iter().enumerate() {
+            let (_, item) = unsafe { &*self.backing[record].0.load(Ordering::Acquire) };
+            if owner.is_none() && item.is_some() {
+                violations.push(Violation::StrayItem { record });
+            }
+            // record 0 stands for null and is claimed for good
+            let claimed = self.claimed[record / BITS].load(Ordering::Acquire) & 1 << (record % BITS) != 0;
+            if record != 0 && claimed != owner.is_some() {
+                violations.push(Violation::ClaimMismatch { record, claimed });
+            }
+        }
+
+        Report { left, right, live, violations }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::{HALF, Word};
+
+    /// Width 7, wrapped round with three items left in it.
+    fn used() -> Joque<u32> {
+        let deque = Joque::new(7);
+        for i in 0..12 {
+            deque.push_back(Box::new(i));
+            if i % 4 != 3 {
+                deque.pop_front();
+            }
+        }
+        deque
+    }
+
+    /// `store`, which the 128-bit word doesn't have.
+    fn put(word: &AtomicWord, val: Word) {
+        let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |_| Some(val));
+    }
+
+    fn slot_of(deque: &Joque<u32>, nth: u32) -> (u32, Word) {
+        let (left, _) = demux(deque.leftright.load(Ordering::Relaxed));
+        let slot = (left + 1 + nth) % deque.capacity;
+        (slot, deque.deque[slot as usize].load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn fresh_and_used_deques_pass() {
+        assert!(Joque::<u32>::new(5).validate().is_ok());
+        let mut deque = used();
+        let report = deque.validate();
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(3, report.live);
+        while deque.pop_back().is_some() {}
+        assert!(deque.validate().is_ok());
+    }
+
+    #[test]
+    fn catches_a_tag_that_moved_on() {
+        let mut deque = used();
+        let (slot, val) = slot_of(&deque, 1);
+        put(&deque.deque[slot as usize], val + (1 << HALF));
+        let report = deque.validate();
+        let record = (val & LEFTMASK) as usize;
+        let slot_tag = generation(val) as u64 + 1;
+        assert_eq!(
+            vec![Violation::TagMismatch { slot, record, slot_tag, record_tag: slot_tag - 1 }],
+            report.violations,
+            "{report}"
+        );
+        put(&deque.deque[slot as usize], val);
+    }
+
+    #[test]
+    fn catches_two_slots_on_one_record() {
+        let mut deque = used();
+        let (first, val) = slot_of(&deque, 0);
+        let (second, lost) = slot_of(&deque, 2);
+        put(&deque.deque[second as usize], val);
+        let report = deque.validate();
+        let lost = (lost & LEFTMASK) as usize;
+        assert!(report.violations.contains(&Violation::SharedRecord { record: (val & LEFTMASK) as usize, slots: (first, second) }));
+        assert!(report.violations.contains(&Violation::StrayItem { record: lost }));
+        assert!(report.violations.contains(&Violation::ClaimMismatch { record: lost, claimed: true }));
+        // put it back so teardown finds every item
+        put(&deque.deque[second as usize], lost as Word | ((val >> HALF) << HALF));
+    }
+
+    #[test]
+    fn catches_slots_left_behind_and_emptied() {
+        let mut deque = used();
+        let (left, right) = demux(deque.leftright.load(Ordering::Relaxed));
+        let (slot, val) = slot_of(&deque, 0);
+        // step the left extent inward past a slot without draining it
+        put(&deque.leftright, crate::mux(slot, right));
+        let report = deque.validate();
+        let record = (val & LEFTMASK) as usize;
+        assert!(report.violations.contains(&Violation::StaleSlot { slot, record }));
+        assert!(report.violations.contains(&Violation::ClaimMismatch { record, claimed: true }));
+        put(&deque.leftright, crate::mux(left, right));
+
+        put(&deque.deque[slot as usize], val & !LEFTMASK);
+        let report = deque.validate();
+        assert!(report.violations.contains(&Violation::EmptyLiveSlot { slot }));
+        assert!(report.violations.contains(&Violation::StrayItem { record }));
+        assert!(report.to_string().contains("slot"), "{report}");
+        put(&deque.deque[slot as usize], val);
+    }
+
+    #[test]
+    fn catches_a_slot_left_marked() {
+        let mut deque = used();
+        let (slot, val) = slot_of(&deque, 2);
+        put(&deque.deque[slot as usize], val | MARK);
+        let report = deque.validate();
+        assert_eq!(vec![Violation::MarkedSlot { slot }], report.violations, "{report}");
+        put(&deque.deque[slot as usize], val);
+    }
+}
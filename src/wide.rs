@@ -0,0 +1,309 @@
+//! 128-bit slot and extent words for the `wide` feature on x86_64.
+//!
+//! With 64-bit halves the op tags stop wrapping in practice, at the cost of a double-width
+//! CAS per operation. Only the tags widen: extents and backing indices still have to fit in
+//! 32 bits, so the ring is capped at 2^30 slots as without the feature.
+//!
+//! `AtomicU128` isn't stable, so this wraps `cmpxchg16b` directly. The first x86_64 chips
+//! shipped without it; on those each word keeps its state in its low 8 bytes instead, as a
+//! plain `AtomicU64` holding the 32/32 layout's worth of bits, and tags wrap at
+//! [`FALLBACK_GENMASK`]. Either way every access is a single lock-free CAS.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub(crate) type Word = u128;
+
+pub(crate) type Tag = u64;
+
+/// How far a slot's generation runs before wrapping when the words fall back to 64 bits:
+/// the upper half keeps its two flag bits and 30 bits of tag, as in the 32/32 layout.
+pub(crate) const FALLBACK_GENMASK: Word = (1 << 30) - 1;
+
+/// A 16-byte-aligned `u128` whose every access goes through `cmpxchg16b`, or through an
+/// `AtomicU64` over its low 8 bytes when the CPU doesn't have it. Orderings are accepted
+/// for parity with the std atomics and otherwise ignored: both paths are sequentially
+/// consistent.
+///
+/// The value is stored [`permute`]d, so that the bits the 64-bit form keeps are the low 8
+/// bytes on both paths and [`new`](Self::new) can stay a `const fn` without knowing which
+/// one the process will take.
+#[repr(C, align(16))]
+pub(crate) struct AtomicWide(UnsafeCell<u128>);
+
+unsafe impl Sync for AtomicWide {}
+
+impl AtomicWide {
+    pub(crate) const fn new(val: u128) -> Self {
+        AtomicWide(UnsafeCell::new(permute(val)))
+    }
+
+    pub(crate) fn load(&self, _order: Ordering) -> u128 {
+        #[cfg(feature = "fault-injection")]
+        crate::faults::step(crate::faults::Sites::LOADS, false);
+        self.peek()
+    }
+
+    pub(crate) fn compare_exchange(
+        &self,
+        current: u128,
+        new: u128,
+        _success: Ordering,
+        _failure: Ordering,
+    ) -> Result<u128, u128> {
+        #[cfg(feature = "fault-injection")]
+        crate::faults::step(crate::faults::Sites::CASES, false);
+        self.cas(current, new)
+    }
+
+    /// The same as [`compare_exchange`](Self::compare_exchange), which never fails
+    /// spuriously, except under `fault-injection`, which may fail it on purpose.
+    pub(crate) fn compare_exchange_weak(
+        &self,
+        current: u128,
+        new: u128,
+        _success: Ordering,
+        _failure: Ordering,
+    ) -> Result<u128, u128> {
+        #[cfg(feature = "fault-injection")]
+        if crate::faults::step(crate::faults::Sites::CASES, true) {
+            return Err(self.peek());
+        }
+        self.cas(current, new)
+    }
+
+    fn peek(&self) -> u128 {
+        self.peek_via(has_cmpxchg16b())
+    }
+
+    fn cas(&self, current: u128, new: u128) -> Result<u128, u128> {
+        self.cas_via(has_cmpxchg16b(), current, new)
+    }
+
+    fn peek_via(&self, cx16: bool) -> u128 {
+        if cx16 {
+            // x86_64 only promises that a 16-byte load is atomic on CPUs with AVX, which this
+            // doesn't check for, so a read is a CAS too: one that only "succeeds" when it
+            // writes back what's already there
+            match self.cas_via(true, 0, 0) {
+                Ok(val) | Err(val) => val,
+            }
+        } else {
+            unpermute(self.low().load(Ordering::SeqCst) as u128)
+        }
+    }
+
+    fn cas_via(&self, cx16: bool, current: u128, new: u128) -> Result<u128, u128> {
+        let (prev, ok) = if cx16 {
+            let (prev, ok) = unsafe { cmpxchg16b(self.0.get(), permute(current), permute(new)) };
+            (unpermute(prev), ok)
+        } else {
+            let (current, new) = (permute(current), permute(new));
+            assert!(new >> 64 == 0, "{new:#x} needs cmpxchg16b to store");
+            if current >> 64 != 0 {
+                // it can't be in there, so there's nothing to compare against
+                return Err(self.peek_via(false));
+            }
+            match self.low().compare_exchange(current as u64, new as u64, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(prev) => (unpermute(prev as u128), true),
+                Err(prev) => (unpermute(prev as u128), false),
+            }
+        };
+        if ok { Ok(prev) } else { Err(prev) }
+    }
+
+    /// The low 8 bytes, which is all the 64-bit path reads or writes.
+    fn low(&self) -> &AtomicU64 {
+        // x86_64 is little-endian, and the cell is 16-byte aligned
+        unsafe { AtomicU64::from_ptr(self.0.get().cast()) }
+    }
+
+    // only the tests use it: `reserve` marks its slot before it CASes the extents
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn fetch_update<F>(
+        &self,
+        _set_order: Ordering,
+        _fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<u128, u128>
+    where
+        F: FnMut(u128) -> Option<u128>,
+    {
+        let mut prev = self.load(Ordering::SeqCst);
+        while let Some(next) = f(prev) {
+            match self.compare_exchange(prev, next, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(val) => return Ok(val),
+                Err(actual) => prev = actual,
+            }
+        }
+        Err(prev)
+    }
+}
+
+const LOW32: u64 = u32::MAX as u64;
+const FLAGS: u64 = 0b11 << 62;
+const TAG30: u64 = (1 << 30) - 1;
+
+/// Rearranges a word so its low 64 bits are what the 32/32 layout would keep: the low 32
+/// bits of the lower half, then 30 bits of the upper half under its two flag bits. The
+/// rest goes in the high 64 bits, which the fallback needs to be zero.
+const fn permute(val: u128) -> u128 {
+    let (lo, hi) = (val as u64, (val >> 64) as u64);
+    let kept = lo & LOW32 | (hi & TAG30) << 32 | hi & FLAGS;
+    let rest = lo >> 32 | (hi >> 30 & LOW32) << 32;
+    (rest as u128) << 64 | kept as u128
+}
+
+const fn unpermute(stored: u128) -> u128 {
+    let (kept, rest) = (stored as u64, (stored >> 64) as u64);
+    let lo = kept & LOW32 | (rest & LOW32) << 32;
+    let hi = kept >> 32 & TAG30 | (rest >> 32) << 30 | kept & FLAGS;
+    (hi as u128) << 64 | lo as u128
+}
+
+/// Whether to use the instruction; decided once per process so every access to a given
+/// word agrees on the path. Without std there's no runtime detection, so it's whatever the
+/// build targets.
+pub(crate) fn has_cmpxchg16b() -> bool {
+    #[cfg(miri)]
+    return false;
+    #[cfg(all(not(miri), feature = "std"))]
+    return std::arch::is_x86_feature_detected!("cmpxchg16b");
+    #[cfg(all(not(miri), not(feature = "std")))]
+    cfg!(target_feature = "cmpxchg16b")
+}
+
+/// # Safety
+/// `dst` is valid, 16-byte aligned, and the CPU supports `cmpxchg16b`.
+#[cfg(not(miri))]
+#[target_feature(enable = "cmpxchg16b")]
+unsafe fn cmpxchg16b(dst: *mut u128, old: u128, new: u128) -> (u128, bool) {
+    let (prev_lo, prev_hi): (u64, u64);
+    unsafe {
+        // rbx can't be named as an operand but the allocator may still hand it out, so
+        // every other operand is pinned and the new low half is swapped through it
+        core::arch::asm!(
+            "xchg {new_lo}, rbx",
+            "lock cmpxchg16b xmmword ptr [rdi]",
+            "mov rbx, {new_lo}",
+            new_lo = inout(reg) new as u64 => _,
+            in("rdi") dst,
+            inout("rax") old as u64 => prev_lo,
+            inout("rdx") (old >> 64) as u64 => prev_hi,
+            in("rcx") (new >> 64) as u64,
+            options(nostack),
+        );
+    }
+    // it stores exactly when what it found was what we expected
+    let prev = ((prev_hi as u128) << 64) | prev_lo as u128;
+    (prev, prev == old)
+}
+
+#[cfg(miri)]
+unsafe fn cmpxchg16b(_dst: *mut u128, _old: u128, _new: u128) -> (u128, bool) {
+    unreachable!("miri always takes the 64-bit path")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "alloc")]
+    use crate::sync::HALF;
+
+    #[test]
+    fn wide_cas_uses_both_halves() {
+        let word = AtomicWide::new(1);
+        let big = (7u128 << 64) | 9;
+        assert_eq!(Err(1), word.compare_exchange(2, big, Ordering::SeqCst, Ordering::SeqCst));
+        assert_eq!(Ok(1), word.compare_exchange(1, big, Ordering::SeqCst, Ordering::SeqCst));
+        assert_eq!(big, word.load(Ordering::SeqCst));
+        assert_eq!(
+            Err(big),
+            word.compare_exchange(9, 0, Ordering::SeqCst, Ordering::SeqCst),
+            "a matching low half alone mustn't do"
+        );
+        assert_eq!(Ok(big), word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| Some(v + (1 << 64))));
+        assert_eq!((8u128 << 64) | 9, word.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn permuting_keeps_the_packed_bits_low() {
+        let packed = [0u128, 5, (3 << 126) | (0x3fff_ffff << 64) | 0xffff_ffff, 1 << 126 | 7 << 64 | 42];
+        for val in packed {
+            assert_eq!(0, permute(val) >> 64, "{val:#x}");
+            assert_eq!(val, unpermute(permute(val)));
+        }
+        for val in [u128::MAX, 1 << 94, 1 << 32, 5u128 << 100] {
+            assert_ne!(0, permute(val) >> 64, "{val:#x}");
+            assert_eq!(val, unpermute(permute(val)));
+        }
+    }
+
+    #[test]
+    fn fallback_matches_instruction() {
+        let word = AtomicWide::new(5 << 64);
+        assert_eq!(Err(5 << 64), word.cas_via(false, 5, 6));
+        assert_eq!(Err(5 << 64), word.cas_via(false, 5u128 << 70, 6), "can't be stored, so can't match");
+        assert_eq!(Ok(5 << 64), word.cas_via(false, 5 << 64, 3 << 126 | 9));
+        assert_eq!(3 << 126 | 9, word.peek_via(false));
+        if has_cmpxchg16b() {
+            assert_eq!(3 << 126 | 9, word.peek_via(true), "both paths read the same bytes");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "needs cmpxchg16b")]
+    fn fallback_refuses_what_it_cant_hold() {
+        let word = AtomicWide::new(0);
+        let _ = word.cas_via(false, 0, 1 << 32);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn tags_run_past_u32() {
+        if !has_cmpxchg16b() {
+            return;
+        }
+        let deque = crate::Joque::new(8);
+        for slot in &deque.deque {
+            let aged = (u32::MAX as Word - 2) << HALF;
+            assert!(slot.compare_exchange(0, aged, Ordering::SeqCst, Ordering::SeqCst).is_ok());
+        }
+        for i in 0..40 {
+            deque.push_back(Box::new(i));
+            assert_eq!(Some(i), deque.pop_front().map(|item| *item));
+        }
+        assert!(deque.deque.iter().all(|slot| slot.load(Ordering::SeqCst) >> HALF > u32::MAX as Word));
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn wide_cas_contended() {
+        use std::sync::Arc;
+
+        for cx16 in [false, true] {
+            if cx16 && !has_cmpxchg16b() {
+                continue;
+            }
+            let word = Arc::new(AtomicWide::new(0));
+            let ths: Vec<_> = (0..8)
+                .map(|_| {
+                    let word = word.clone();
+                    std::thread::spawn(move || {
+                        for _ in 0..1000 {
+                            // bump both halves together; a torn update would split them
+                            let mut prev = word.peek_via(cx16);
+                            while let Err(actual) = word.cas_via(cx16, prev, prev + (1 << 64) + 1) {
+                                prev = actual;
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for th in ths {
+                th.join().unwrap();
+            }
+            assert_eq!((8000u128 << 64) | 8000, word.peek_via(cx16));
+        }
+    }
+}
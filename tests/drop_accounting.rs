@@ -0,0 +1,286 @@
+//! Drop accounting: every item pushed into a `Joque` is dropped exactly once, whether it
+//! leaves through a pop, a rejected push, or teardown, and every allocation the deque makes
+//! along the way is handed back by the time it's gone.
+//!
+//! Lives in its own test binary because it installs a counting global allocator.
+#![cfg(all(not(loom), feature = "alloc"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+
+use joque::Joque;
+
+/// Counts live allocations made by threads that have opted in with [`tracked`].
+///
+/// Every allocation carries a small header recording whether it was tracked, so a
+/// tracked allocation freed on some other thread is still counted off.
+struct CountingAlloc;
+
+const HEADER: usize = 16;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn padded(layout: Layout) -> (Layout, usize) {
+    let offset = HEADER.max(layout.align());
+    let padded = Layout::from_size_align(layout.size() + offset, layout.align().max(HEADER))
+        .expect("layout overflow");
+    (padded, offset)
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (padded, offset) = padded(layout);
+        unsafe {
+            let base = System.alloc(padded);
+            if base.is_null() {
+                return base;
+            }
+            let tracked = TRACKING.try_with(|t| t.get()).unwrap_or(false);
+            if tracked {
+                LIVE.fetch_add(1, Ordering::SeqCst);
+            }
+            *base = tracked as u8;
+            base.add(offset)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (padded, offset) = padded(layout);
+        unsafe {
+            let base = ptr.sub(offset);
+            if *base == 1 {
+                LIVE.fetch_sub(1, Ordering::SeqCst);
+            }
+            System.dealloc(base, padded);
+        }
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Runs `f` with this thread's allocations counted in [`LIVE`].
+fn tracked<R>(f: impl FnOnce() -> R) -> R {
+    // with `trace`, a thread's first operation hands it an event ring that outlives every
+    // deque, so let that happen before counting starts
+    #[cfg(feature = "trace")]
+    drop(Joque::<()>::new(5).pop_front());
+    TRACKING.with(|t| t.set(true));
+    let out = f();
+    TRACKING.with(|t| t.set(false));
+    out
+}
+
+/// Runs `f` expecting one of its items to go off, then resumes tracking.
+fn expect_panic(f: impl FnOnce()) {
+    let unwound = std::panic::catch_unwind(AssertUnwindSafe(f));
+    TRACKING.with(|t| t.set(true));
+    assert!(unwound.is_err(), "nothing went off");
+}
+
+/// One counter per item id, bumped by [`Counted`]'s drop.
+struct Tally(Vec<AtomicU8>);
+
+impl Tally {
+    fn new(items: usize) -> Arc<Tally> {
+        Arc::new(Tally((0..items).map(|_| AtomicU8::new(0)).collect()))
+    }
+
+    fn item(self: &Arc<Tally>, id: usize) -> Box<Counted> {
+        Box::new(Counted(id, self.clone(), false))
+    }
+
+    /// An item whose destructor panics after it has been counted.
+    fn bomb(self: &Arc<Tally>, id: usize) -> Box<Counted> {
+        Box::new(Counted(id, self.clone(), true))
+    }
+
+    fn assert_each_dropped_once(&self) {
+        for (id, drops) in self.0.iter().enumerate() {
+            assert_eq!(1, drops.load(Ordering::SeqCst), "item {id} dropped the wrong number of times");
+        }
+    }
+}
+
+struct Counted(usize, Arc<Tally>, bool);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.1.0[self.0].fetch_add(1, Ordering::SeqCst);
+        if self.2 {
+            // the panic machinery allocates (and caches) on its own account
+            TRACKING.with(|t| t.set(false));
+            panic!("item {} went off", self.0);
+        }
+    }
+}
+
+/// The allocator count is process-wide, so scenarios take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Builds the tally untracked, runs `scenario` tracked, then checks drops and allocations.
+fn account(items: usize, scenario: impl FnOnce(&Arc<Tally>)) {
+    let _turn = SERIAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let tally = Tally::new(items);
+    let before = LIVE.load(Ordering::SeqCst);
+    tracked(|| scenario(&tally));
+    tally.assert_each_dropped_once();
+    assert_eq!(before, LIVE.load(Ordering::SeqCst), "allocations outlived the deque");
+}
+
+#[test]
+fn popped_items_drop_once() {
+    account(6, |tally| {
+        let deque = Joque::new(8);
+        for id in 0..3 {
+            deque.push_front(tally.item(id));
+        }
+        for id in 3..6 {
+            deque.push_back(tally.item(id));
+        }
+        for _ in 0..3 {
+            drop(deque.pop_front());
+            drop(deque.pop_back());
+        }
+        assert!(deque.is_empty());
+    });
+}
+
+#[test]
+fn teardown_drops_queued_items() {
+    account(10, |tally| {
+        let deque = Joque::new(16);
+        for id in 0..10 {
+            if id % 2 == 0 {
+                deque.push_front(tally.item(id));
+            } else {
+                deque.push_back(tally.item(id));
+            }
+        }
+        drop(deque.pop_back());
+        drop(deque);
+    });
+}
+
+#[test]
+fn rejected_push_hands_item_back() {
+    account(5, |tally| {
+        let deque = Joque::new(5);
+        for id in 0..4 {
+            deque.push_back(tally.item(id));
+        }
+        let bounced = deque.try_push_front(tally.item(4)).unwrap_err();
+        drop(bounced);
+    });
+}
+
+#[test]
+fn wrap_drops_once() {
+    // many more pushes than the ring has slots or backing has records
+    let cycles = 500;
+    account(cycles * 2 + 3, |tally| {
+        let deque = Joque::new(7);
+        for id in 0..cycles {
+            deque.push_front(tally.item(id * 2));
+            deque.push_back(tally.item(id * 2 + 1));
+            drop(deque.pop_back());
+            drop(deque.pop_back());
+        }
+        for id in cycles * 2..cycles * 2 + 3 {
+            deque.push_back(tally.item(id));
+        }
+    });
+}
+
+#[test]
+fn concurrent_drops_once() {
+    let threads = 8;
+    let per_thread = 200;
+    account(threads * per_thread, |tally| {
+        let deque = Arc::new(Joque::new(64));
+        let start = Arc::new(Barrier::new(threads));
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let deque = deque.clone();
+                let tally = tally.clone();
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    start.wait();
+                    tracked(|| {
+                        for i in 0..per_thread {
+                            let id = t * per_thread + i;
+                            let item = tally.item(id);
+                            let rejected = if id % 2 == 0 {
+                                deque.try_push_front(item)
+                            } else {
+                                deque.try_push_back(item)
+                            };
+                            drop(rejected);
+                            if i % 3 == 0 {
+                                drop(deque.pop_front());
+                            } else if i % 3 == 1 {
+                                drop(deque.pop_back());
+                            }
+                        }
+                    });
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        drop(Arc::into_inner(deque).expect("workers still hold the deque"));
+    });
+}
+
+#[test]
+fn teardown_survives_panicking_drop() {
+    account(9, |tally| {
+        let deque = Joque::new(16);
+        for id in 0..4 {
+            deque.push_back(tally.item(id));
+        }
+        deque.push_back(tally.bomb(4));
+        for id in 5..9 {
+            deque.push_back(tally.item(id));
+        }
+        expect_panic(move || drop(deque));
+    });
+}
+
+#[test]
+fn deque_usable_after_popped_item_panics() {
+    account(4, |tally| {
+        let deque = Joque::new(8);
+        deque.push_front(tally.bomb(0));
+        deque.push_back(tally.item(1));
+        expect_panic(|| drop(deque.pop_front()));
+
+        assert_eq!(1, deque.len());
+        deque.push_front(tally.item(2));
+        deque.push_back(tally.item(3));
+        assert_eq!(2, deque.pop_front().unwrap().0);
+        assert_eq!(3, deque.pop_back().unwrap().0);
+        assert_eq!(1, deque.pop_back().unwrap().0);
+        assert!(deque.is_empty());
+    });
+}
+
+#[test]
+fn unsized_items_drop_once() {
+    account(6, |tally| {
+        let deque: Joque<dyn Send> = Joque::new(8);
+        for id in 0..6 {
+            deque.push_back(tally.item(id));
+        }
+        drop(deque.pop_front());
+        drop(deque.pop_back());
+    });
+}
@@ -0,0 +1,210 @@
+//! Loom models of every pair and triple of operations racing on a `Joque`.
+//!
+//! Each model starts the deque out empty, one item in, or two, then has one thread per
+//! operation race. The near-empty starts are where the two ends compete for the last item.
+//! Once they're done the main thread pops whatever's left. Whatever the interleaving, what
+//! each thread got and what was left must be what running the operations one at a time, in
+//! some order, would have given, and every item pushed must be dropped exactly once.
+//!
+//! The pairs are exhaustive: every interleaving of the two threads is explored. The triples
+//! are not. They run under a preemption bound, which `LOOM_MAX_PREEMPTIONS` doesn't change,
+//! so each only covers the interleavings with that many forced switches or fewer; see
+//! [`triples`] for why.
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom_models
+//! ```
+#![cfg(all(loom, feature = "alloc"))]
+
+use std::collections::VecDeque;
+
+use loom::sync::{Arc, Mutex};
+use loom::thread;
+
+use joque::Joque;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    PushFront,
+    PopFront,
+    PushBack,
+    PopBack,
+}
+
+use Op::*;
+
+const OPS: [Op; 4] = [PushFront, PopFront, PushBack, PopBack];
+
+/// An item that logs its id to a shared list when it's dropped.
+struct Token {
+    id: u32,
+    dropped: Arc<Mutex<Vec<u32>>>,
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        self.dropped.lock().unwrap().push(self.id);
+    }
+}
+
+/// Every multiset of `n` operations, each listed once in `OPS` order.
+fn combos(n: usize) -> Vec<Vec<Op>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+    let mut out = vec![];
+    for shorter in combos(n - 1) {
+        let from = shorter.last().map_or(0, |last| OPS.iter().position(|op| op == last).unwrap());
+        for &op in &OPS[from..] {
+            let mut combo = shorter.clone();
+            combo.push(op);
+            out.push(combo);
+        }
+    }
+    out
+}
+
+/// Every order of `0..n`.
+fn orders(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+    let mut out = vec![];
+    for shorter in orders(n - 1) {
+        for at in 0..=shorter.len() {
+            let mut order = shorter.clone();
+            order.insert(at, n - 1);
+            out.push(order);
+        }
+    }
+    out
+}
+
+/// Whether running `ops` one at a time, in some order, on a deque holding `0..prefill`
+/// gives thread `t` what it `got[t]` and leaves `rest` behind.
+fn sequential(prefill: u32, ops: &[Op], got: &[Option<u32>], rest: &[u32]) -> bool {
+    orders(ops.len()).into_iter().any(|order| {
+        let mut deque: VecDeque<u32> = (0..prefill).collect();
+        let matches = order.iter().all(|&t| {
+            let item = 100 + t as u32;
+            let out = match ops[t] {
+                PushFront => {
+                    deque.push_front(item);
+                    None
+                }
+                PushBack => {
+                    deque.push_back(item);
+                    None
+                }
+                PopFront => deque.pop_front(),
+                PopBack => deque.pop_back(),
+            };
+            out == got[t]
+        });
+        matches && deque.iter().eq(rest)
+    })
+}
+
+/// Races `ops`, one per thread, on a deque holding `prefill` items, and checks the outcome
+/// is a sequential one and every item comes out exactly once. Explores every interleaving,
+/// or with `preemptions` set, only those with up to that many forced switches.
+fn model(prefill: u32, ops: &[Op], preemptions: Option<usize>) {
+    let ops = ops.to_vec();
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = preemptions;
+    builder.check(move || {
+        let dropped = Arc::new(Mutex::new(vec![]));
+        let token = |id| Box::new(Token { id, dropped: dropped.clone() });
+        // room for two prefilled items and three pushes
+        let deque = Arc::new(Joque::new(6));
+        for id in 0..prefill {
+            deque.push_back(token(id));
+        }
+
+        let ths: Vec<_> = ops
+            .iter()
+            .enumerate()
+            .map(|(t, &op)| {
+                let deque = deque.clone();
+                let item = token(100 + t as u32);
+                thread::spawn(move || match op {
+                    PushFront => {
+                        deque.push_front(item);
+                        None
+                    }
+                    PushBack => {
+                        deque.push_back(item);
+                        None
+                    }
+                    PopFront => deque.pop_front().map(|token| token.id),
+                    PopBack => deque.pop_back().map(|token| token.id),
+                })
+            })
+            .collect();
+        let got: Vec<Option<u32>> = ths.into_iter().map(|th| th.join().unwrap()).collect();
+        let popped: Vec<u32> = got.iter().flatten().copied().collect();
+
+        let pushes = ops.iter().filter(|op| matches!(op, PushFront | PushBack)).count();
+        let pops = ops.len() - pushes;
+        let total = prefill as usize + pushes;
+        assert_eq!(total - popped.len(), deque.len(), "{ops:?} from {prefill}: len is off");
+        // a pop only comes back empty if the deque could have been empty when it ran
+        assert!(popped.len() >= pops.min(prefill as usize), "{ops:?} from {prefill}: pops came back short");
+
+        let mut rest = vec![];
+        while let Some(token) = deque.pop_front() {
+            rest.push(token.id);
+        }
+        assert!(
+            sequential(prefill, &ops, &got, &rest),
+            "{ops:?} from {prefill}: no order of the operations gives {got:?} and leaves {rest:?}"
+        );
+
+        // pushes this model didn't use, then popped tokens
+        drop(deque);
+        let mut dropped = Arc::try_unwrap(dropped).unwrap().into_inner().unwrap();
+        dropped.sort();
+        let mut expected: Vec<u32> = (0..prefill).collect();
+        expected.extend((0..ops.len() as u32).map(|t| 100 + t));
+        assert_eq!(expected, dropped, "{ops:?} from {prefill}: items lost or dropped twice");
+
+        let mut unique = popped.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(popped.len(), unique.len(), "{ops:?} from {prefill}: popped twice");
+    });
+}
+
+/// Unbounded, so every interleaving of every pair; the slowest take a few seconds each.
+#[test]
+fn pairs() {
+    for prefill in 0..=2 {
+        for ops in combos(2) {
+            model(prefill, &ops, None);
+        }
+    }
+}
+
+/// Bounded at one preemption, and not exhaustive. Unbounded, a single triple hadn't finished
+/// after five minutes. The bound can't go up to two either, and not for lack of time: with
+/// two, a thread descheduled while it holds a slot's mark can leave the other two yielding
+/// to each other on it, and loom counts every such round as a new branch until it gives up
+/// on the model, which it does for about half the triples. One is still enough for any two
+/// of the three to meet in the middle of each other's operation.
+#[test]
+fn triples() {
+    for prefill in 0..=2 {
+        for ops in combos(3) {
+            model(prefill, &ops, Some(1));
+        }
+    }
+}
+
+/// The two triples here are among those loom can finish with two preemptions, so they get
+/// them.
+#[test]
+fn last_item_from_both_ends() {
+    model(1, &[PopFront, PopBack], None);
+    model(1, &[PopFront, PopBack, PushFront], Some(2));
+    model(1, &[PopFront, PopBack, PushBack], Some(2));
+}
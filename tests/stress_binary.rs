@@ -0,0 +1,29 @@
+//! Runs the `joque-stress` binary briefly, to keep it building and keep it honest.
+#![cfg(all(feature = "std", not(loom), not(miri)))]
+
+use std::process::Command;
+
+#[test]
+fn short_soak_finds_nothing() {
+    let out = Command::new(env!("CARGO_BIN_EXE_joque-stress"))
+        .args(["--threads", "4", "--width", "16", "--mix", "3,1,1,2", "--duration", "2", "--interval", "1"])
+        .args(["--seed", "7", "--eliminating", "2", "--combining", "4"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{stdout}{}", String::from_utf8_lossy(&out.stderr));
+    assert!(stdout.starts_with("joque-stress: 4 threads, width 16, mix [3, 1, 1, 2], seed 7, 2s\n"), "{stdout}");
+    assert!(stdout.lines().filter(|line| line.contains("Mops/s")).count() >= 2, "{stdout}");
+    assert!(!stdout.contains("anomaly"), "{stdout}");
+}
+
+#[test]
+fn rejects_bad_flags() {
+    let bin = env!("CARGO_BIN_EXE_joque-stress");
+    for args in [&["--mix", "1,2"][..], &["--threads", "many"], &["--bogus", "1"], &["--seed"], &["--width", "4"], &["--width", "2000000000"]] {
+        let out = Command::new(bin).args(args).output().unwrap();
+        assert_eq!(Some(2), out.status.code(), "{args:?}");
+        assert!(String::from_utf8_lossy(&out.stderr).contains("usage: joque-stress"));
+    }
+    assert!(Command::new(bin).arg("--help").output().unwrap().status.success());
+}